@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::credentials::REDACTED;
+
+/// Keys that hold text in the .dockerwsl schema, a number or `true`/`false` set for them is kept as text.
+const TEXT_KEYS: [&str; 13] = ["name", "image", "latest", "tag", "base_install_path", "default_base_wsl_install_path", "archive",
+    "windows_terminal_profile_id", "username", "tenant", "range", "include", "exclude"];
+
+/// Sets the value at `path` in a .dockerwsl document, as in `dragon config set wsls.dev.latest 1.2.0`.
+///
+/// `path` is a list of keys separated by dots. Items of a list are picked by their `name`, like `wsls.dev`, or by their
+/// position, like `private_registries.0` for registries whose name has dots in it. Missing keys are added. `raw_value` is read as yaml, so `5`, `null` or `{ env: ACR_PASSWORD }` work. Where there is
+/// a text value already or the schema has text, a number or `true`/`false` is kept as text, so tags like `1.10` stay as they are.
+pub fn set_value(dockerwsl_document: &mut Value, path: &str, raw_value: &str) -> Result<()> {
+    let keys: Vec<&str> = path.split('.').collect();
+    if keys.iter().any(|key| key.is_empty()) {
+        return Err(anyhow::anyhow!("`{}` is not a valid path, use keys separated by dots like `wsls.dev.latest`!", path));
+    }
+
+    let (last_key, parent_keys) = keys.split_last().unwrap();
+    let mut parent = dockerwsl_document;
+    for (depth, key) in parent_keys.iter().enumerate() {
+        parent = child_mut(parent, key, true)
+            .with_context(|| format!("There is nothing at `{}` in .dockerwsl!", keys[..=depth].join(".")))?;
+    }
+
+    let parsed_value: Value = serde_yaml::from_str(raw_value)
+        .with_context(|| format!("`{}` is not a valid yaml value!", raw_value))?;
+    let is_text = TEXT_KEYS.contains(last_key) || matches!(child_mut(parent, last_key, false), Some(Value::String(_)));
+    let new_value = match parsed_value {
+        Value::Number(_) | Value::Bool(_) if is_text => Value::String(raw_value.to_string()),
+        parsed_value => parsed_value
+    };
+
+    match parent {
+        Value::Mapping(mapping) => {
+            mapping.insert(Value::from(*last_key), new_value);
+        },
+        Value::Sequence(_) => {
+            let item = child_mut(parent, last_key, false)
+                .with_context(|| format!("There is no item `{}` in the list at `{}`!", last_key, parent_keys.join(".")))?;
+            *item = new_value;
+        },
+        _ => return Err(anyhow::anyhow!("`{}` is not a mapping or a list, so it has no `{}`!", parent_keys.join("."), last_key))
+    }
+
+    Ok(())
+}
+
+/// Replaces the passwords written in .dockerwsl itself with `***`. References to environment variables and files are kept.
+pub fn redact_secrets(dockerwsl_document: &mut Value) {
+    let private_registries = match dockerwsl_document.get_mut("private_registries").and_then(Value::as_sequence_mut) {
+        Some(private_registries) => private_registries,
+        None => return
+    };
+
+    for private_registry in private_registries.iter_mut() {
+        if let Some(password) = private_registry.get_mut("password") {
+            if password.is_string() {
+                *password = Value::from(REDACTED);
+            }
+        }
+    }
+}
+
+/// The value under `key`: the value of a mapping key, or the item of a list with that `name` or at that position.
+/// With `create`, a missing mapping key is added with an empty mapping.
+fn child_mut<'a>(parent: &'a mut Value, key: &str, create: bool) -> Option<&'a mut Value> {
+    match parent {
+        Value::Mapping(mapping) => {
+            let key = Value::from(key);
+            if create && mapping.get(&key).is_none_or(Value::is_null) {
+                mapping.insert(key.clone(), Value::Mapping(Mapping::new()));
+            }
+            mapping.get_mut(&key)
+        },
+        Value::Sequence(items) => {
+            let named_index = items.iter().position(|item| item.get("name").and_then(Value::as_str) == Some(key));
+            match named_index.or_else(|| key.parse::<usize>().ok()) {
+                Some(index) => items.get_mut(index),
+                None => None
+            }
+        },
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn sets_values_by_path() {
+        let mut dockerwsl_document = document("version: 1\nwsls:\n  - name: dev\n    latest: '1.9'\n  - name: web\nprivate_registries: []\n");
+
+        set_value(&mut dockerwsl_document, "wsls.dev.latest", "1.10").unwrap();
+        set_value(&mut dockerwsl_document, "wsls.1.keep_versions", "3").unwrap();
+        set_value(&mut dockerwsl_document, "default_windows_terminal_profile.font.size", "11").unwrap();
+        set_value(&mut dockerwsl_document, "wsls.web.windows_terminal_profile", "{ icon: 'C:\\icons\\web.png' }").unwrap();
+
+        assert_eq!(dockerwsl_document, document("version: 1\nwsls:\n  - name: dev\n    latest: '1.10'\n  - name: web\n    keep_versions: 3\n    windows_terminal_profile: { icon: 'C:\\icons\\web.png' }\nprivate_registries: []\ndefault_windows_terminal_profile:\n  font: { size: 11 }\n"));
+
+        assert!(set_value(&mut dockerwsl_document, "wsls.api.latest", "2").is_err());
+        assert!(set_value(&mut dockerwsl_document, "wsls.5", "2").is_err());
+        assert!(set_value(&mut dockerwsl_document, "version.major", "2").is_err());
+        assert!(set_value(&mut dockerwsl_document, "wsls..latest", "2").is_err());
+    }
+
+    #[test]
+    fn keeps_numbers_as_text_where_the_schema_has_text() {
+        let mut dockerwsl_document = document("version: 1\nwsls:\n  - name: dev\n");
+
+        set_value(&mut dockerwsl_document, "wsls.dev.latest", "1.10").unwrap();
+        set_value(&mut dockerwsl_document, "wsls.dev.keep_versions", "3").unwrap();
+
+        assert_eq!(dockerwsl_document, document("version: 1\nwsls:\n  - name: dev\n    latest: '1.10'\n    keep_versions: 3\n"));
+    }
+
+    #[test]
+    fn redacts_plain_passwords_only() {
+        let mut dockerwsl_document = document("private_registries:\n  - name: a.azurecr.io\n    password: hunter2\n  - name: b.azurecr.io\n    password: { env: ACR_PASSWORD }\n");
+
+        redact_secrets(&mut dockerwsl_document);
+
+        assert_eq!(dockerwsl_document, document("private_registries:\n  - name: a.azurecr.io\n    password: '***'\n  - name: b.azurecr.io\n    password: { env: ACR_PASSWORD }\n"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::{Serialize, Deserialize};
+
+pub const REDACTED: &str = "***";
+
+/// A secret value that never shows up in debug output, which is what `-vvv` logs.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", REDACTED)
+    }
+}
+
+impl FromStr for Secret {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(Secret(value.to_string()))
+    }
+}
+
+/// Where the password of a private registry comes from, as written in .dockerwsl:
+/// `password: { env: ACR_PASSWORD }`, `password: { file: 'C:\secrets\acr' }` or, kept for older .dockerwsl files, the password itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SecretRef {
+    Env { env: String },
+    File { file: String },
+    Plain(Secret)
+}
+
+impl SecretRef {
+    pub fn resolve(&self) -> Result<Secret> {
+        match self {
+            SecretRef::Env { env } => std::env::var(env)
+                .map(Secret)
+                .with_context(|| format!("Environment variable `{}` is not set!", env)),
+            SecretRef::File { file } => std::fs::read_to_string(file)
+                .map(|content| Secret(content.trim_end_matches(['\r', '\n']).to_string()))
+                .with_context(|| format!("Could not read secret file `{}`!", file)),
+            SecretRef::Plain(secret) => Ok(secret.clone())
+        }
+    }
+}
+
+/// Username and secret as returned by `docker-credential-<helper> get`.
+#[derive(Debug, Deserialize)]
+struct CredentialHelperOutput {
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: Secret
+}
+
+/// Gets the credentials of `server_url` from a credential helper following the docker-credential-helpers protocol.
+///
+/// `helper` is either a helper name like `wincred`, which runs `docker-credential-wincred`, or the path to the helper binary.
+pub fn get_from_credential_helper(helper: &str, server_url: &str) -> Result<(String, Secret)> {
+    let helper_binary = if helper.contains('/') || helper.contains('\\') { helper.to_string() } else { format!("docker-credential-{}", helper) };
+
+    let mut helper_process = Command::new(&helper_binary)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run credential helper `{}`!", &helper_binary))?;
+
+    helper_process.stdin.take().unwrap().write_all(server_url.as_bytes())
+        .with_context(|| format!("Could not pass the server URL to credential helper `{}`!", &helper_binary))?;
+
+    let helper_output = helper_process.wait_with_output()
+        .with_context(|| format!("Credential helper `{}` failed!", &helper_binary))?;
+    if !helper_output.status.success() {
+        // the helper prints errors like `credentials not found in native keychain` on stdout
+        return Err(anyhow::anyhow!("Credential helper `{}` has no credentials for `{}`: {}", &helper_binary, server_url,
+            String::from_utf8_lossy(&helper_output.stdout).trim()));
+    }
+
+    let credentials: CredentialHelperOutput = serde_json::from_slice(&helper_output.stdout)
+        .with_context(|| format!("Could not parse the output of credential helper `{}`!", &helper_binary))?;
+
+    Ok((credentials.username, credentials.secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_and_resolves_secret_refs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let secret_file_path = temp_dir.path().join("acr");
+        std::fs::write(&secret_file_path, "from-file\r\n").unwrap();
+        std::env::set_var("DRAGON_TEST_REGISTRY_PASSWORD", "from-env");
+
+        let yaml = format!("- plain-password\n- env: DRAGON_TEST_REGISTRY_PASSWORD\n- file: '{}'", secret_file_path.display());
+        let secret_refs: Vec<SecretRef> = serde_yaml::from_str(&yaml).unwrap();
+
+        let resolved: Vec<String> = secret_refs.iter().map(|secret_ref| secret_ref.resolve().unwrap().expose().to_string()).collect();
+        assert_eq!(resolved, vec!["plain-password", "from-env", "from-file"]);
+
+        assert!(SecretRef::Env { env: "DRAGON_TEST_UNSET_VARIABLE".to_string() }.resolve().is_err());
+    }
+
+    #[test]
+    fn redacts_secrets_from_debug_output() {
+        let secret_refs = vec![SecretRef::Plain(Secret("hunter2".to_string())), SecretRef::Env { env: "ACR_PASSWORD".to_string() }];
+
+        let debug_output = format!("{:?} {:#?}", secret_refs, secret_refs);
+        assert!(!debug_output.contains("hunter2"));
+        assert!(debug_output.contains(REDACTED));
+        assert!(debug_output.contains("ACR_PASSWORD"));
+
+        let helper_output: CredentialHelperOutput = serde_json::from_str(r#"{"ServerURL":"acr.io","Username":"sp-id","Secret":"sp-secret"}"#).unwrap();
+        assert!(!format!("{:?}", helper_output).contains("sp-secret"));
+        assert_eq!(helper_output.secret.expose(), "sp-secret");
+    }
+}
@@ -0,0 +1,107 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Unchanged lines shown around each change in a file diff.
+const DIFF_CONTEXT_LINES: usize = 2;
+
+/// Set once from `--dry-run`, checked right before anything on the machine is changed: WSL VMs, install folders,
+/// image pulls, .dockerwsl, the lock file and the Windows Terminal settings.json and fragment files.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    DRY_RUN.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Prints how writing `new_content` would change the file, instead of writing it.
+pub fn print_file_change(file_path: &Path, new_content: &str) {
+    let (old_content, action) = match std::fs::read_to_string(file_path) {
+        Ok(old_content) => (old_content, "change"),
+        Err(_) => (String::new(), "create")
+    };
+
+    let diff = diff_lines(&old_content, new_content);
+    if diff.is_empty() {
+        message!("Would leave `{}` as it is.", file_path.display());
+        return;
+    }
+
+    message!("Would {} `{}`:", action, file_path.display());
+    for line in diff.iter() {
+        message!("    {}", line);
+    }
+}
+
+/// A line diff of the two texts: removed lines start with `-`, added ones with `+`, and `...` stands for unchanged lines left out.
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // common_lines[i][j] is the length of the longest common subsequence of old_lines[i..] and new_lines[j..]
+    let mut common_lines = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            common_lines[i][j] = if old_lines[i] == new_lines[j] {
+                common_lines[i + 1][j + 1] + 1
+            } else {
+                common_lines[i + 1][j].max(common_lines[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old_lines.len() || j < new_lines.len() {
+        if i < old_lines.len() && j < new_lines.len() && old_lines[i] == new_lines[j] {
+            changes.push((' ', old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if i < old_lines.len() && (j == new_lines.len() || common_lines[i + 1][j] >= common_lines[i][j + 1]) {
+            changes.push(('-', old_lines[i]));
+            i += 1;
+        } else {
+            changes.push(('+', new_lines[j]));
+            j += 1;
+        }
+    }
+
+    let changed_indexes: Vec<usize> = changes.iter().enumerate()
+        .filter(|(_index, (marker, _line))| *marker != ' ')
+        .map(|(index, _change)| index)
+        .collect();
+
+    let mut diff = vec![];
+    let mut last_shown_index: Option<usize> = None;
+    for (index, (marker, line)) in changes.iter().enumerate() {
+        if !changed_indexes.iter().any(|changed_index| changed_index.abs_diff(index) <= DIFF_CONTEXT_LINES) {
+            continue;
+        }
+
+        if index > last_shown_index.map_or(0, |last_shown_index| last_shown_index + 1) {
+            diff.push("...".to_string());
+        }
+        diff.push(format!("{} {}", marker, line).trim_end().to_string());
+        last_shown_index = Some(index);
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_lines_with_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\n";
+
+        assert_eq!(diff_lines(old, new), vec!["...", "  c", "  d", "- e", "+ E", "  f", "  g", "  h", "  i", "+ j"]);
+        assert_eq!(diff_lines("", "x: 1\n"), vec!["+ x: 1"]);
+        assert_eq!(diff_lines("x: 1\ny: 2\n", ""), vec!["- x: 1", "- y: 2"]);
+        assert!(diff_lines(old, old).is_empty());
+    }
+}
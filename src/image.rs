@@ -0,0 +1,643 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::progress::{Progress, ProgressWriter};
+use crate::registry::RegistryClient;
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Downloads the layers of an image from its registry and flattens them into a single rootfs tar in `temp_dir`,
+/// the same tar `docker container export` would produce. No container runtime is needed.
+pub fn export_image_to_tar(registry_client: &mut RegistryClient, repository_name: &str, reference: &str, temp_dir: &Path) -> Result<PathBuf> {
+    let manifest = registry_client.get_manifest(repository_name, reference)
+        .with_context(|| format!("Could not get the manifest of image `{}:{}`!", repository_name, reference))?;
+
+    let layers = get_layers(&manifest)
+        .with_context(|| format!("Could not get the layers of image `{}:{}`!", repository_name, reference))?;
+
+    let mut layer_paths = vec![];
+    for (index, (layer_digest, layer_size)) in layers.iter().enumerate() {
+        let mut progress = Progress::start(&format!("Downloading layer {}/{} `{}`", index + 1, layers.len(), layer_digest), *layer_size);
+
+        let layer_path = temp_dir.join(format!("layer-{}", index));
+        download_blob(registry_client, repository_name, layer_digest, &layer_path, &mut progress)
+            .with_context(|| format!("Could not download layer `{}`!", layer_digest))?;
+        layer_paths.push(layer_path);
+
+        progress.finish();
+    }
+
+    let rootfs_tar_path = temp_dir.join("rootfs.tar");
+    flatten_layers(&layer_paths, &rootfs_tar_path)
+        .with_context(|| format!("Could not flatten the layers of image `{}:{}`!", repository_name, reference))?;
+
+    Ok(rootfs_tar_path)
+}
+
+/// An image stored locally, either a `docker save` tarball or an OCI image layout (directory or tar).
+pub struct ImageArchive {
+    /// Full image reference found in the archive (e.g. `myacr.azurecr.io/dev:1.0`), if any.
+    pub image_name: Option<String>,
+    /// Manifest digest of the image, only known for OCI image layouts.
+    pub digest: Option<String>,
+    layer_paths: Vec<PathBuf>
+}
+
+impl ImageArchive {
+    /// Reads the archive at `archive_path`, tarballs are unpacked to `temp_dir` first.
+    pub fn open(archive_path: &Path, temp_dir: &Path) -> Result<ImageArchive> {
+        let archive_dir = if archive_path.is_dir() {
+            archive_path.to_path_buf()
+        } else {
+            let archive_dir = temp_dir.join("archive");
+            let archive_file = File::open(archive_path)
+                .with_context(|| format!("Could not open image archive `{}`!", archive_path.display()))?;
+
+            message!("Unpacking image archive `{}`...", archive_path.display());
+            tar::Archive::new(BufReader::new(archive_file)).unpack(&archive_dir)
+                .with_context(|| format!("Could not unpack image archive `{}`!", archive_path.display()))?;
+            archive_dir
+        };
+
+        if archive_dir.join("manifest.json").is_file() {
+            ImageArchive::open_docker_save(&archive_dir)
+        } else if archive_dir.join("index.json").is_file() {
+            ImageArchive::open_oci_layout(&archive_dir)
+        } else {
+            Err(anyhow::anyhow!("`{}` is neither a `docker save` tarball nor an OCI image layout!", archive_path.display()))
+        }
+    }
+
+    fn open_docker_save(archive_dir: &Path) -> Result<ImageArchive> {
+        let manifest = read_json_file(&archive_dir.join("manifest.json"))?;
+        let images = manifest.as_array()
+            .with_context(|| "`manifest.json` of the `docker save` tarball is not an array!")?;
+
+        if images.len() > 1 {
+            message!("Image archive contains {} images, only the first one is used!", images.len());
+        }
+        let image = images.first()
+            .with_context(|| "Image archive doesn't contain any image!")?;
+
+        let image_name = image.get("RepoTags").and_then(|t| t.as_array())
+            .and_then(|t| t.first()).and_then(|t| t.as_str())
+            .map(|t| t.to_string());
+        let layer_paths = image.get("Layers").and_then(|l| l.as_array())
+            .with_context(|| "`manifest.json` of the `docker save` tarball doesn't have layers!")?
+            .iter()
+            .map(|layer| layer.as_str().map(|l| archive_dir.join(l)).with_context(|| "`manifest.json` has an invalid layer!"))
+            .collect::<Result<Vec<PathBuf>>>()?;
+
+        Ok(ImageArchive { image_name, digest: None, layer_paths })
+    }
+
+    fn open_oci_layout(archive_dir: &Path) -> Result<ImageArchive> {
+        let index = read_json_file(&archive_dir.join("index.json"))?;
+        let image_descriptor = index.get("manifests").and_then(|m| m.as_array()).and_then(|m| m.first())
+            .with_context(|| "`index.json` of the OCI image layout doesn't contain any image!")?;
+
+        let annotations = image_descriptor.get("annotations");
+        let annotation = |name: &str| annotations.and_then(|a| a.get(name)).and_then(|a| a.as_str()).map(|a| a.to_string());
+        // `org.opencontainers.image.ref.name` is often only the tag, so it's used only if it looks like a full reference
+        let image_name = annotation("io.containerd.image.name")
+            .or_else(|| annotation("org.opencontainers.image.ref.name").filter(|name| name.contains('/')));
+        let digest = image_descriptor.get("digest").and_then(|d| d.as_str()).map(|d| d.to_string());
+
+        let mut manifest = read_oci_blob(archive_dir, image_descriptor)?;
+        while let Some(manifests) = manifest.get("manifests").and_then(|m| m.as_array()).cloned() {
+            let image_manifest = manifests.iter()
+                .find(|m| m.get("platform").and_then(|p| p.get("architecture")).and_then(|a| a.as_str()) == Some("amd64"))
+                .or_else(|| manifests.first())
+                .with_context(|| "OCI image index is empty!")?;
+            manifest = read_oci_blob(archive_dir, image_manifest)?;
+        }
+
+        let layer_paths = get_layers(&manifest)?
+            .iter()
+            .map(|(digest, _size)| get_oci_blob_path(archive_dir, digest))
+            .collect::<Result<Vec<PathBuf>>>()?;
+
+        Ok(ImageArchive { image_name, digest, layer_paths })
+    }
+
+    /// Flattens the image layers into a rootfs tar in `temp_dir`.
+    pub fn export_to_tar(&self, temp_dir: &Path) -> Result<PathBuf> {
+        let rootfs_tar_path = temp_dir.join("rootfs.tar");
+
+        flatten_layers(&self.layer_paths, &rootfs_tar_path)
+            .with_context(|| "Could not flatten the layers of the image archive!")?;
+
+        Ok(rootfs_tar_path)
+    }
+}
+
+fn read_json_file(file_path: &Path) -> Result<serde_json::Value> {
+    let file = File::open(file_path)
+        .with_context(|| format!("Could not open `{}`!", file_path.display()))?;
+
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Could not parse `{}`!", file_path.display()))
+}
+
+fn get_oci_blob_path(archive_dir: &Path, digest: &str) -> Result<PathBuf> {
+    let (algorithm, hash) = digest.split_once(':')
+        .with_context(|| format!("`{}` is not a valid digest!", digest))?;
+
+    Ok(archive_dir.join("blobs").join(algorithm).join(hash))
+}
+
+fn read_oci_blob(archive_dir: &Path, descriptor: &serde_json::Value) -> Result<serde_json::Value> {
+    let digest = descriptor.get("digest").and_then(|d| d.as_str())
+        .with_context(|| "OCI descriptor doesn't have a digest!")?;
+
+    read_json_file(&get_oci_blob_path(archive_dir, digest)?)
+}
+
+/// Digest and, if the manifest has it, size in bytes of every layer.
+fn get_layers(manifest: &serde_json::Value) -> Result<Vec<(String, Option<u64>)>> {
+    let layers = manifest.get("layers").and_then(|l| l.as_array())
+        .with_context(|| "Manifest doesn't have a `layers` property, only schema 2 and OCI manifests are supported!")?;
+
+    layers.iter()
+        .map(|layer| {
+            let digest = layer.get("digest").and_then(|d| d.as_str()).map(|d| d.to_string())
+                .with_context(|| "Manifest has a layer without digest!")?;
+            Ok((digest, layer.get("size").and_then(|s| s.as_u64())))
+        })
+        .collect()
+}
+
+/// Downloads a blob to `blob_path` and verifies its sha256 digest.
+fn download_blob(registry_client: &mut RegistryClient, repository_name: &str, digest: &str, blob_path: &Path, progress: &mut Progress) -> Result<()> {
+    let mut blob_reader = registry_client.get_blob(repository_name, digest)?;
+    let mut blob_writer = BufWriter::new(File::create(blob_path)
+        .with_context(|| format!("Could not create file `{}`!", blob_path.display()))?);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = blob_reader.read(&mut buffer)
+            .with_context(|| format!("Could not read blob `{}`!", digest))?;
+        if read == 0 { break; }
+
+        hasher.update(&buffer[..read]);
+        blob_writer.write_all(&buffer[..read])
+            .with_context(|| format!("Could not write file `{}`!", blob_path.display()))?;
+        progress.advance(read as u64);
+    }
+    blob_writer.flush()?;
+
+    let actual_digest = format!("sha256:{:x}", hasher.finalize());
+    if digest.starts_with("sha256:") && actual_digest != digest {
+        return Err(anyhow::anyhow!("Blob digest mismatch, expected `{}` but got `{}`!", digest, actual_digest));
+    }
+
+    Ok(())
+}
+
+/// Applies the layers (ordered from the base layer up) on top of each other and writes the result as one tar.
+///
+/// Layers are walked top-down to decide which entries survive: an entry is hidden by the same path in an upper layer,
+/// by an upper `.wh.<name>` whiteout of itself or a parent, by an upper non-directory at a parent path, or by an upper
+/// `.wh..wh..opq` opaque whiteout in its parent directory. The surviving entries are then written bottom-up, so hard
+/// links always come after their targets.
+pub fn flatten_layers(layer_paths: &[PathBuf], output_tar_path: &Path) -> Result<()> {
+    let mut kept_entries: Vec<Vec<bool>> = vec![vec![]; layer_paths.len()];
+    let mut upper_paths: HashSet<String> = HashSet::new();
+    let mut upper_non_dirs: HashSet<String> = HashSet::new();
+    let mut upper_whiteouts: HashSet<String> = HashSet::new();
+    let mut upper_opaque_dirs: HashSet<String> = HashSet::new();
+
+    for (layer_index, layer_path) in layer_paths.iter().enumerate().rev() {
+        let mut layer_paths_seen = vec![];
+        let mut layer_non_dirs = vec![];
+        let mut layer_whiteouts = vec![];
+        let mut layer_opaque_dirs = vec![];
+
+        let mut layer_archive = tar::Archive::new(open_layer(layer_path)?);
+        for entry in layer_archive.entries().with_context(|| format!("Could not read layer `{}`!", layer_path.display()))? {
+            let entry = entry.with_context(|| format!("Could not read an entry of layer `{}`!", layer_path.display()))?;
+            let entry_path = normalize_entry_path(&entry.path()?);
+            let (parent, name) = split_entry_path(&entry_path);
+
+            if name == OPAQUE_WHITEOUT {
+                layer_opaque_dirs.push(parent.to_string());
+                kept_entries[layer_index].push(false);
+                continue;
+            }
+            if let Some(hidden_name) = name.strip_prefix(WHITEOUT_PREFIX) {
+                layer_whiteouts.push(join_entry_path(parent, hidden_name));
+                kept_entries[layer_index].push(false);
+                continue;
+            }
+
+            let hidden = entry_path.is_empty()
+                || upper_paths.contains(&entry_path)
+                || is_hidden_by(&entry_path, &upper_whiteouts, true)
+                || is_hidden_by(&entry_path, &upper_non_dirs, false)
+                || is_hidden_by(&entry_path, &upper_opaque_dirs, false);
+            kept_entries[layer_index].push(!hidden);
+
+            if !hidden {
+                if !entry.header().entry_type().is_dir() {
+                    layer_non_dirs.push(entry_path.clone());
+                }
+                layer_paths_seen.push(entry_path);
+            }
+        }
+
+        // whiteouts and opaque directories only hide entries of the layers below
+        upper_paths.extend(layer_paths_seen);
+        upper_non_dirs.extend(layer_non_dirs);
+        upper_whiteouts.extend(layer_whiteouts);
+        upper_opaque_dirs.extend(layer_opaque_dirs);
+    }
+
+    let output_tar_file = File::create(output_tar_path)
+        .with_context(|| format!("Could not create tar file `{}`!", output_tar_path.display()))?;
+    let mut progress = Progress::start(&format!("Writing `{}`", output_tar_path.display()), None);
+    let mut output_tar = tar::Builder::new(ProgressWriter::new(BufWriter::new(output_tar_file), &mut progress));
+
+    for (layer_index, layer_path) in layer_paths.iter().enumerate() {
+        debug!("Writing the entries of layer `{}` to `{}`.", layer_path.display(), output_tar_path.display());
+
+        let mut layer_archive = tar::Archive::new(open_layer(layer_path)?);
+        for (entry_index, entry) in layer_archive.entries()?.enumerate() {
+            let mut entry = entry?;
+            if !kept_entries[layer_index][entry_index] {
+                continue;
+            }
+
+            let entry_path = normalize_entry_path(&entry.path()?);
+            let mut header = entry.header().clone();
+
+            match entry.link_name()? {
+                Some(link_name) if header.entry_type().is_hard_link() || header.entry_type().is_symlink() => {
+                    let link_name = if header.entry_type().is_hard_link() {
+                        PathBuf::from(normalize_entry_path(&link_name))
+                    } else {
+                        link_name.into_owned()
+                    };
+                    output_tar.append_link(&mut header, &entry_path, &link_name)
+                        .with_context(|| format!("Could not write link `{}` to the tar file!", &entry_path))?;
+                },
+                _ => {
+                    output_tar.append_data(&mut header, &entry_path, &mut entry)
+                        .with_context(|| format!("Could not write `{}` to the tar file!", &entry_path))?;
+                }
+            }
+        }
+    }
+
+    output_tar.into_inner()?.into_inner().flush()?;
+    progress.finish();
+
+    Ok(())
+}
+
+/// Layers are either gzip compressed or plain tars.
+fn open_layer(layer_path: &Path) -> Result<Box<dyn Read>> {
+    let mut layer_file = BufReader::new(File::open(layer_path)
+        .with_context(|| format!("Could not open layer `{}`!", layer_path.display()))?);
+
+    let mut magic = [0u8; 4];
+    let magic_len = layer_file.read(&mut magic)?;
+    let layer_reader = std::io::Cursor::new(magic[..magic_len].to_vec()).chain(layer_file);
+
+    if magic_len >= 2 && magic[..2] == [0x1f, 0x8b] {
+        Ok(Box::new(GzDecoder::new(layer_reader)))
+    } else if magic_len == 4 && magic == [0x28, 0xb5, 0x2f, 0xfd] {
+        Err(anyhow::anyhow!("Layer `{}` is zstd compressed, which is not supported!", layer_path.display()))
+    } else {
+        Ok(Box::new(layer_reader))
+    }
+}
+
+/// `./usr/bin/`, `/usr/bin` and `usr/bin` all become `usr/bin`.
+fn normalize_entry_path(entry_path: &Path) -> String {
+    entry_path.components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn split_entry_path(entry_path: &str) -> (&str, &str) {
+    match entry_path.rfind('/') {
+        Some(index) => (&entry_path[..index], &entry_path[index + 1..]),
+        None => ("", entry_path)
+    }
+}
+
+fn join_entry_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() { name.to_string() } else { format!("{}/{}", parent, name) }
+}
+
+/// Whether any parent directory of `entry_path` (or the path itself, if `include_self`) is in `paths`.
+fn is_hidden_by(entry_path: &str, paths: &HashSet<String>, include_self: bool) -> bool {
+    if paths.is_empty() {
+        return false;
+    }
+    if include_self && paths.contains(entry_path) {
+        return true;
+    }
+
+    let mut parent = entry_path;
+    while let Some(index) = parent.rfind('/') {
+        parent = &parent[..index];
+        if paths.contains(parent) {
+            return true;
+        }
+    }
+
+    // the root directory is the parent of top-level entries
+    !include_self && paths.contains("") && !entry_path.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{start_mock_server, MockResponse};
+    use flate2::write::GzEncoder;
+    use std::collections::HashMap;
+
+    enum FixtureEntry<'a> {
+        Dir(&'a str),
+        File(&'a str, &'a str),
+        Symlink(&'a str, &'a str),
+        HardLink(&'a str, &'a str)
+    }
+
+    fn build_layer(entries: &[FixtureEntry], gzip: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+
+        for entry in entries.iter() {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o755);
+            match entry {
+                FixtureEntry::Dir(path) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, std::io::empty()).unwrap();
+                },
+                FixtureEntry::File(path, content) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(content.len() as u64);
+                    builder.append_data(&mut header, path, content.as_bytes()).unwrap();
+                },
+                FixtureEntry::Symlink(path, target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, path, target).unwrap();
+                },
+                FixtureEntry::HardLink(path, target) => {
+                    header.set_entry_type(tar::EntryType::Link);
+                    header.set_size(0);
+                    builder.append_link(&mut header, path, target).unwrap();
+                }
+            }
+        }
+
+        let layer = builder.into_inner().unwrap();
+        if gzip {
+            let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(&layer).unwrap();
+            encoder.finish().unwrap()
+        } else {
+            layer
+        }
+    }
+
+    fn sha256_digest(blob: &[u8]) -> String {
+        format!("sha256:{:x}", Sha256::digest(blob))
+    }
+
+    /// Reads the rootfs tar as path -> file content (or `dir` / `-> target` / `=> target` for links).
+    fn read_rootfs(tar_path: &Path) -> HashMap<String, String> {
+        let mut archive = tar::Archive::new(File::open(tar_path).unwrap());
+        let mut rootfs = HashMap::new();
+
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let entry_type = entry.header().entry_type();
+            let value = if entry_type.is_dir() {
+                "dir".to_string()
+            } else if entry_type.is_symlink() {
+                format!("-> {}", entry.link_name().unwrap().unwrap().display())
+            } else if entry_type.is_hard_link() {
+                format!("=> {}", entry.link_name().unwrap().unwrap().display())
+            } else {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                content
+            };
+            assert!(rootfs.insert(path.clone(), value).is_none(), "`{}` is in the rootfs tar twice", path);
+        }
+
+        rootfs
+    }
+
+    fn flatten_fixture(layers: Vec<Vec<u8>>) -> HashMap<String, String> {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let layer_paths: Vec<PathBuf> = layers.iter().enumerate().map(|(index, layer)| {
+            let layer_path = temp_dir.path().join(format!("layer-{}", index));
+            std::fs::write(&layer_path, layer).unwrap();
+            layer_path
+        }).collect();
+
+        let output_path = temp_dir.path().join("rootfs.tar");
+        flatten_layers(&layer_paths, &output_path).unwrap();
+        read_rootfs(&output_path)
+    }
+
+    #[test]
+    fn upper_layers_override_lower_layers() {
+        let rootfs = flatten_fixture(vec![
+            build_layer(&[FixtureEntry::Dir("./etc/"), FixtureEntry::File("./etc/os-release", "base"), FixtureEntry::File("./etc/hosts", "hosts")], true),
+            build_layer(&[FixtureEntry::Dir("etc"), FixtureEntry::File("etc/os-release", "dev")], false)
+        ]);
+
+        assert_eq!(rootfs.get("etc/os-release").unwrap(), "dev");
+        assert_eq!(rootfs.get("etc/hosts").unwrap(), "hosts");
+        assert_eq!(rootfs.get("etc").unwrap(), "dir");
+        assert_eq!(rootfs.len(), 3);
+    }
+
+    #[test]
+    fn whiteouts_remove_files_and_directories() {
+        let rootfs = flatten_fixture(vec![
+            build_layer(&[
+                FixtureEntry::Dir("tmp"), FixtureEntry::File("tmp/cache", "cache"),
+                FixtureEntry::Dir("opt"), FixtureEntry::Dir("opt/tool"), FixtureEntry::File("opt/tool/bin", "bin"),
+                FixtureEntry::File("keep", "keep")
+            ], true),
+            build_layer(&[FixtureEntry::Dir("tmp"), FixtureEntry::File("tmp/.wh.cache", ""), FixtureEntry::Dir("opt"), FixtureEntry::File("opt/.wh.tool", "")], true),
+            build_layer(&[FixtureEntry::Dir("opt"), FixtureEntry::Dir("opt/tool"), FixtureEntry::File("opt/tool/new", "new")], true)
+        ]);
+
+        assert!(!rootfs.contains_key("tmp/cache"));
+        assert!(!rootfs.contains_key("tmp/.wh.cache"));
+        assert!(!rootfs.contains_key("opt/tool/bin"));
+        assert_eq!(rootfs.get("opt/tool/new").unwrap(), "new");
+        assert_eq!(rootfs.get("keep").unwrap(), "keep");
+        assert_eq!(rootfs.get("tmp").unwrap(), "dir");
+    }
+
+    #[test]
+    fn opaque_directories_hide_lower_contents() {
+        let rootfs = flatten_fixture(vec![
+            build_layer(&[FixtureEntry::Dir("app"), FixtureEntry::File("app/old", "old"), FixtureEntry::Dir("app/lib"), FixtureEntry::File("app/lib/a", "a")], true),
+            build_layer(&[FixtureEntry::Dir("app"), FixtureEntry::File("app/.wh..wh..opq", ""), FixtureEntry::File("app/new", "new")], true)
+        ]);
+
+        assert_eq!(rootfs.get("app").unwrap(), "dir");
+        assert_eq!(rootfs.get("app/new").unwrap(), "new");
+        assert!(!rootfs.contains_key("app/old"));
+        assert!(!rootfs.contains_key("app/lib"));
+        assert!(!rootfs.contains_key("app/lib/a"));
+        assert!(!rootfs.contains_key("app/.wh..wh..opq"));
+    }
+
+    #[test]
+    fn files_replacing_directories_hide_their_contents() {
+        let rootfs = flatten_fixture(vec![
+            build_layer(&[FixtureEntry::Dir("data"), FixtureEntry::File("data/a", "a")], true),
+            build_layer(&[FixtureEntry::Symlink("data", "/mnt/data")], true)
+        ]);
+
+        assert_eq!(rootfs.get("data").unwrap(), "-> /mnt/data");
+        assert!(!rootfs.contains_key("data/a"));
+    }
+
+    #[test]
+    fn keeps_links_after_their_targets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let layer_path = temp_dir.path().join("layer-0");
+        std::fs::write(&layer_path, build_layer(&[
+            FixtureEntry::Dir("bin"), FixtureEntry::File("bin/busybox", "busybox"),
+            FixtureEntry::HardLink("bin/sh", "./bin/busybox"), FixtureEntry::Symlink("bin/ls", "busybox")
+        ], true)).unwrap();
+
+        let output_path = temp_dir.path().join("rootfs.tar");
+        flatten_layers(&[layer_path], &output_path).unwrap();
+
+        let rootfs = read_rootfs(&output_path);
+        assert_eq!(rootfs.get("bin/sh").unwrap(), "=> bin/busybox");
+        assert_eq!(rootfs.get("bin/ls").unwrap(), "-> busybox");
+    }
+
+    #[test]
+    fn exports_image_from_registry_to_rootfs_tar() {
+        let base_layer = build_layer(&[FixtureEntry::Dir("etc"), FixtureEntry::File("etc/motd", "hello"), FixtureEntry::File("etc/old", "old")], true);
+        let top_layer = build_layer(&[FixtureEntry::File("etc/.wh.old", ""), FixtureEntry::File("etc/motd", "dragon")], false);
+        let layers = vec![base_layer, top_layer];
+        let layer_digests: Vec<String> = layers.iter().map(|layer| sha256_digest(layer)).collect();
+        let blobs: HashMap<String, Vec<u8>> = layer_digests.iter().cloned().zip(layers).collect();
+
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "config": { "digest": "sha256:config" },
+            "layers": layer_digests.iter().map(|digest| serde_json::json!({ "digest": digest })).collect::<Vec<serde_json::Value>>()
+        });
+        let (base_url, _) = start_mock_server(move |request, _| {
+            match request.path.strip_prefix("/v2/library/dev/") {
+                Some("manifests/1.0") => MockResponse::json(200, manifest.clone()),
+                Some(blob) if blob.starts_with("blobs/") => MockResponse::bytes(blobs.get(&blob["blobs/".len()..]).unwrap().clone()),
+                _ => MockResponse::json(404, serde_json::json!({}))
+            }
+        });
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(Some(base_url.trim_start_matches("http://")), None);
+        let tar_path = export_image_to_tar(&mut client, "library/dev", "1.0", temp_dir.path()).unwrap();
+
+        let rootfs = read_rootfs(&tar_path);
+        assert_eq!(rootfs.get("etc/motd").unwrap(), "dragon");
+        assert!(!rootfs.contains_key("etc/old"));
+    }
+
+    fn build_archive(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in files.iter() {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, content.as_slice()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn exports_docker_save_tarball() {
+        let base_layer = build_layer(&[FixtureEntry::Dir("etc"), FixtureEntry::File("etc/motd", "hello")], false);
+        let top_layer = build_layer(&[FixtureEntry::File("etc/motd", "dragon")], false);
+        let manifest = serde_json::json!([{
+            "Config": "config.json",
+            "RepoTags": ["myacr.azurecr.io/dev:1.0"],
+            "Layers": ["base/layer.tar", "top/layer.tar"]
+        }]);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("dev.tar");
+        std::fs::write(&archive_path, build_archive(&[
+            ("manifest.json", manifest.to_string().into_bytes()),
+            ("base/layer.tar", base_layer),
+            ("top/layer.tar", top_layer)
+        ])).unwrap();
+
+        let image_archive = ImageArchive::open(&archive_path, temp_dir.path()).unwrap();
+        let rootfs = read_rootfs(&image_archive.export_to_tar(temp_dir.path()).unwrap());
+
+        assert_eq!(image_archive.image_name, Some("myacr.azurecr.io/dev:1.0".to_string()));
+        assert_eq!(rootfs.get("etc/motd").unwrap(), "dragon");
+    }
+
+    #[test]
+    fn exports_oci_image_layout_directory() {
+        let layer = build_layer(&[FixtureEntry::Dir("etc"), FixtureEntry::File("etc/motd", "oci")], true);
+        let layer_digest = sha256_digest(&layer);
+        let manifest = serde_json::json!({ "schemaVersion": 2, "config": { "digest": "sha256:config" }, "layers": [{ "digest": layer_digest }] }).to_string();
+        let manifest_digest = sha256_digest(manifest.as_bytes());
+        let index = serde_json::json!({ "schemaVersion": 2, "manifests": [{
+            "digest": manifest_digest,
+            "annotations": { "io.containerd.image.name": "ghcr.io/team/dev:2.0", "org.opencontainers.image.ref.name": "2.0" }
+        }]});
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let layout_dir = temp_dir.path().join("layout");
+        std::fs::create_dir_all(layout_dir.join("blobs/sha256")).unwrap();
+        std::fs::write(layout_dir.join("index.json"), index.to_string()).unwrap();
+        std::fs::write(layout_dir.join("blobs/sha256").join(&manifest_digest["sha256:".len()..]), manifest).unwrap();
+        std::fs::write(layout_dir.join("blobs/sha256").join(&layer_digest["sha256:".len()..]), layer).unwrap();
+
+        let image_archive = ImageArchive::open(&layout_dir, temp_dir.path()).unwrap();
+        let rootfs = read_rootfs(&image_archive.export_to_tar(temp_dir.path()).unwrap());
+
+        assert_eq!(image_archive.image_name, Some("ghcr.io/team/dev:2.0".to_string()));
+        assert_eq!(image_archive.digest, Some(manifest_digest.clone()));
+        assert_eq!(rootfs.get("etc/motd").unwrap(), "oci");
+    }
+
+    #[test]
+    fn rejects_blobs_with_wrong_digest() {
+        let (base_url, _) = start_mock_server(|_, _| MockResponse::bytes(b"tampered".to_vec()));
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut client = RegistryClient::new(Some(base_url.trim_start_matches("http://")), None);
+        let mut progress = Progress::start("Downloading layer 1/1", None);
+        let result = download_blob(&mut client, "dev", &sha256_digest(b"original"), &temp_dir.path().join("blob"), &mut progress);
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Runs `job` on every item, on up to `jobs` threads at once, and returns what it returned in the order of the items.
+///
+/// Once `stop_after` is true for what a job returned, no other item is started, the ones already running are finished.
+/// Items that were never started have nothing in the returned list.
+pub fn run<T, R, F, S>(jobs: usize, items: Vec<T>, job: F, stop_after: S) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+    S: Fn(&R) -> bool + Sync
+{
+    let thread_count = jobs.clamp(1, items.len().max(1));
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let stopped = AtomicBool::new(false);
+    let outcomes = Mutex::new(vec![]);
+
+    thread::scope(|scope| {
+        for _ in 0..thread_count {
+            scope.spawn(|| loop {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let next_item = queue.lock().unwrap().next();
+                let (index, item) = match next_item {
+                    Some(next_item) => next_item,
+                    None => break
+                };
+
+                let outcome = job(item);
+                if stop_after(&outcome) {
+                    stopped.store(true, Ordering::SeqCst);
+                }
+                outcomes.lock().unwrap().push((index, outcome));
+            });
+        }
+    });
+
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _outcome)| *index);
+    outcomes.into_iter().map(|(_index, outcome)| outcome).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn returns_outcomes_in_item_order() {
+        let items: Vec<u64> = (1..=8).collect();
+
+        // later items finish first
+        let outcomes = run(4, items, |item| {
+            thread::sleep(Duration::from_millis(40 - item * 5));
+            item * 10
+        }, |_outcome| false);
+
+        assert_eq!(outcomes, vec![10, 20, 30, 40, 50, 60, 70, 80]);
+        assert!(run(3, Vec::<u64>::new(), |item| item, |_outcome| false).is_empty());
+    }
+
+    #[test]
+    fn starts_no_item_after_a_stop() {
+        let outcomes = run(1, vec![1, 2, 3, 4], |item| item, |outcome| *outcome == 2);
+
+        assert_eq!(outcomes, vec![1, 2]);
+    }
+}
@@ -0,0 +1,509 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+/// A JSON with comments document, like the Windows Terminal settings.json, that is edited in place.
+///
+/// Only the text of the edited array element changes, comments, trailing commas, key order and indentation
+/// everywhere else are kept byte for byte.
+#[derive(Debug)]
+pub struct JsoncDocument {
+    text: String,
+    root: Node
+}
+
+#[derive(Debug)]
+struct Node {
+    start: usize,
+    end: usize,
+    kind: NodeKind
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Object(Vec<Member>),
+    Array(Vec<Node>),
+    Scalar(serde_json::Value)
+}
+
+#[derive(Debug)]
+struct Member {
+    key: String,
+    key_start: usize,
+    value: Node
+}
+
+impl JsoncDocument {
+    pub fn parse(text: String) -> Result<JsoncDocument> {
+        let root = Parser::new(&text).parse_document()?;
+
+        Ok(JsoncDocument { text, root })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The elements of the array at `path`, e.g. `["profiles", "list"]`, with comments left out.
+    pub fn array_elements(&self, path: &[&str]) -> Result<Vec<serde_json::Value>> {
+        let elements = self.find_array(path)?.1;
+
+        Ok(elements.iter().map(Node::to_value).collect())
+    }
+
+    /// Inserts `value` as the first element of the array at `path`, indented like the elements already in it.
+    pub fn prepend_array_element(&mut self, path: &[&str], value: &serde_json::Value) -> Result<()> {
+        let (array, elements) = self.find_array(path)?;
+        let newline = self.newline();
+        let indent_unit = self.indent_unit(array, elements);
+
+        let new_text = match elements.first() {
+            Some(first_element) => {
+                let element_indent = self.line_indent(first_element.start);
+                let formatted_value = format_value(value, element_indent, &indent_unit, newline)?;
+                let insertion = format!("{},{}{}", formatted_value, newline, element_indent);
+
+                splice(&self.text, first_element.start, first_element.start, &insertion)
+            },
+            None => {
+                let array_indent = self.line_indent(array.start);
+                let element_indent = format!("{}{}", array_indent, indent_unit);
+                let formatted_value = format_value(value, &element_indent, &indent_unit, newline)?;
+                let inner_start = array.start + 1;
+                let inner_end = array.end - 1;
+
+                // an empty `[]` gets the usual layout, anything else in it (comments) is kept after the new element
+                if self.text[inner_start..inner_end].trim().is_empty() {
+                    let replacement = format!("{}{}{}{}{}", newline, element_indent, formatted_value, newline, array_indent);
+                    splice(&self.text, inner_start, inner_end, &replacement)
+                } else {
+                    let insertion = format!("{}{}{}", newline, element_indent, formatted_value);
+                    splice(&self.text, inner_start, inner_start, &insertion)
+                }
+            }
+        };
+
+        self.replace_text(new_text)
+    }
+
+    /// Replaces the element at `index` of the array at `path` with `value`, keeping its position and indentation.
+    pub fn replace_array_element(&mut self, path: &[&str], index: usize, value: &serde_json::Value) -> Result<()> {
+        let (array, elements) = self.find_array(path)?;
+        let element = elements.get(index)
+            .with_context(|| format!("`{}` has no element {}!", path.join("."), index))?;
+
+        let indent_unit = self.indent_unit(array, elements);
+        let formatted_value = format_value(value, self.line_indent(element.start), &indent_unit, self.newline())?;
+        let new_text = splice(&self.text, element.start, element.end, &formatted_value);
+
+        self.replace_text(new_text)
+    }
+
+    /// Removes the element at `index` of the array at `path`, together with its comma and, if it was on lines of its own, those lines.
+    pub fn remove_array_element(&mut self, path: &[&str], index: usize) -> Result<()> {
+        let elements = self.find_array(path)?.1;
+        let element = elements.get(index)
+            .with_context(|| format!("`{}` has no element {}!", path.join("."), index))?;
+
+        let mut start = element.start;
+        let line_start = self.text[..start].rfind('\n').map_or(0, |newline_position| newline_position + 1);
+        if self.text[line_start..start].trim().is_empty() {
+            start = line_start;
+        }
+
+        let mut end = element.end;
+        let after_element = self.skip_trivia(end);
+        let has_trailing_comma = self.text[after_element..].starts_with(',');
+        if has_trailing_comma {
+            end = after_element + 1;
+        }
+
+        let rest_of_line = &self.text[end..];
+        let rest_of_line = &rest_of_line[..rest_of_line.find('\n').map_or(rest_of_line.len(), |newline_position| newline_position + 1)];
+        if start == line_start && rest_of_line.trim().is_empty() {
+            end += rest_of_line.len();
+        }
+
+        let mut new_text = splice(&self.text, start, end, "");
+
+        // without a comma of its own, the element was the last one and the comma in front of it has to go
+        if !has_trailing_comma && index > 0 {
+            let comma_position = self.skip_trivia(elements[index - 1].end);
+            if self.text[comma_position..].starts_with(',') {
+                new_text = splice(&new_text, comma_position, comma_position + 1, "");
+            }
+        }
+
+        self.replace_text(new_text)
+    }
+
+    fn replace_text(&mut self, text: String) -> Result<()> {
+        *self = JsoncDocument::parse(text).with_context(|| "The edited document is not valid anymore!")?;
+
+        Ok(())
+    }
+
+    fn find_array(&self, path: &[&str]) -> Result<(&Node, &Vec<Node>)> {
+        let mut node = &self.root;
+
+        for key in path.iter() {
+            node = match &node.kind {
+                NodeKind::Object(members) => members.iter().rev().find(|member| &member.key == key).map(|member| &member.value),
+                _ => None
+            }.with_context(|| format!("There is no `{}` property!", path.join(".")))?;
+        }
+
+        match &node.kind {
+            NodeKind::Array(elements) => Ok((node, elements)),
+            _ => Err(anyhow::anyhow!("The `{}` property is not an array!", path.join(".")))
+        }
+    }
+
+    fn newline(&self) -> &'static str {
+        if self.text.contains("\r\n") { "\r\n" } else { "\n" }
+    }
+
+    /// The whitespace the line containing `position` starts with.
+    fn line_indent(&self, position: usize) -> &str {
+        let line_start = self.text[..position].rfind('\n').map_or(0, |newline_position| newline_position + 1);
+        let line = &self.text[line_start..];
+
+        &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+    }
+
+    /// How much deeper each nesting level is indented, taken from the existing elements where possible.
+    fn indent_unit(&self, array: &Node, elements: &[Node]) -> String {
+        if let Some(first_element) = elements.first() {
+            let element_indent = self.line_indent(first_element.start);
+
+            if let NodeKind::Object(members) = &first_element.kind {
+                let member_unit = members.first()
+                    .and_then(|member| self.line_indent(member.key_start).strip_prefix(element_indent));
+                if let Some(member_unit) = member_unit.filter(|unit| !unit.is_empty()) {
+                    return member_unit.to_string();
+                }
+            }
+
+            if let Some(element_unit) = element_indent.strip_prefix(self.line_indent(array.start)).filter(|unit| !unit.is_empty()) {
+                return element_unit.to_string();
+            }
+        }
+
+        "    ".to_string()
+    }
+
+    /// The position of the next character that is neither whitespace nor part of a comment.
+    fn skip_trivia(&self, position: usize) -> usize {
+        let mut parser = Parser::new(&self.text);
+        parser.position = position;
+
+        match parser.skip_trivia() {
+            Ok(()) => parser.position,
+            Err(_) => position
+        }
+    }
+}
+
+impl Node {
+    fn to_value(&self) -> serde_json::Value {
+        match &self.kind {
+            NodeKind::Object(members) => serde_json::Value::Object(
+                members.iter().map(|member| (member.key.clone(), member.value.to_value())).collect()
+            ),
+            NodeKind::Array(elements) => serde_json::Value::Array(elements.iter().map(Node::to_value).collect()),
+            NodeKind::Scalar(value) => value.clone()
+        }
+    }
+}
+
+fn splice(text: &str, start: usize, end: usize, replacement: &str) -> String {
+    format!("{}{}{}", &text[..start], replacement, &text[end..])
+}
+
+/// Pretty prints `value` for a line that starts with `indent`.
+fn format_value(value: &serde_json::Value, indent: &str, indent_unit: &str, newline: &str) -> Result<String> {
+    let mut buffer = vec![];
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent_unit.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buffer, formatter);
+    value.serialize(&mut serializer).with_context(|| "Could not serialize json value!")?;
+
+    let formatted_value = String::from_utf8(buffer).with_context(|| "Could not serialize json value!")?;
+
+    Ok(formatted_value.replace('\n', &format!("{}{}", newline, indent)))
+}
+
+/// Parses JSON with `//` and `/* */` comments and trailing commas, remembering where every value is.
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Parser<'a> {
+        Parser { text, bytes: text.as_bytes(), position: 0 }
+    }
+
+    fn parse_document(&mut self) -> Result<Node> {
+        if self.text.starts_with('\u{feff}') {
+            self.position = '\u{feff}'.len_utf8();
+        }
+
+        let root = self.parse_value()?;
+        self.skip_trivia()?;
+
+        if self.position < self.bytes.len() {
+            return Err(self.error("Unexpected content after the end of the document"));
+        }
+
+        Ok(root)
+    }
+
+    fn parse_value(&mut self) -> Result<Node> {
+        self.skip_trivia()?;
+        let start = self.position;
+
+        let kind = match self.bytes.get(start) {
+            Some(b'{') => NodeKind::Object(self.parse_object()?),
+            Some(b'[') => NodeKind::Array(self.parse_array()?),
+            Some(b'"') => NodeKind::Scalar(serde_json::Value::String(self.parse_string()?)),
+            Some(_) => NodeKind::Scalar(self.parse_literal()?),
+            None => return Err(self.error("Unexpected end of the document"))
+        };
+
+        Ok(Node { start, end: self.position, kind })
+    }
+
+    fn parse_object(&mut self) -> Result<Vec<Member>> {
+        let mut members = vec![];
+        self.position += 1;
+
+        loop {
+            self.skip_trivia()?;
+
+            match self.bytes.get(self.position) {
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(members);
+                },
+                Some(b'"') => {},
+                _ => return Err(self.error("Expected a property name or `}`"))
+            }
+
+            let key_start = self.position;
+            let key = self.parse_string()?;
+
+            self.skip_trivia()?;
+            if self.bytes.get(self.position) != Some(&b':') {
+                return Err(self.error("Expected `:`"));
+            }
+            self.position += 1;
+
+            let value = self.parse_value()?;
+            members.push(Member { key, key_start, value });
+
+            self.skip_separator(b'}')?;
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Vec<Node>> {
+        let mut elements = vec![];
+        self.position += 1;
+
+        loop {
+            self.skip_trivia()?;
+
+            if self.bytes.get(self.position) == Some(&b']') {
+                self.position += 1;
+                return Ok(elements);
+            }
+
+            elements.push(self.parse_value()?);
+
+            self.skip_separator(b']')?;
+        }
+    }
+
+    /// Skips the `,` after a member or element, which is optional before the closing bracket.
+    fn skip_separator(&mut self, closing_bracket: u8) -> Result<()> {
+        self.skip_trivia()?;
+
+        match self.bytes.get(self.position) {
+            Some(b',') => {
+                self.position += 1;
+                Ok(())
+            },
+            Some(byte) if *byte == closing_bracket => Ok(()),
+            _ => Err(self.error(&format!("Expected `,` or `{}`", closing_bracket as char)))
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        let start = self.position;
+        self.position += 1;
+
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b'"') => break,
+                Some(b'\\') => self.position += 2,
+                Some(_) => self.position += 1,
+                None => return Err(self.error("Unterminated string"))
+            }
+        }
+        self.position += 1;
+
+        serde_json::from_str(&self.text[start..self.position])
+            .map_err(|error| self.error(&format!("Invalid string ({})", error)))
+    }
+
+    fn parse_literal(&mut self) -> Result<serde_json::Value> {
+        let start = self.position;
+
+        while let Some(byte) = self.bytes.get(self.position) {
+            if matches!(byte, b',' | b']' | b'}' | b'/' | b' ' | b'\t' | b'\r' | b'\n') {
+                break;
+            }
+            self.position += 1;
+        }
+
+        serde_json::from_str(&self.text[start..self.position])
+            .map_err(|_| self.error(&format!("Invalid value `{}`", &self.text[start..self.position])))
+    }
+
+    fn skip_trivia(&mut self) -> Result<()> {
+        loop {
+            match (self.bytes.get(self.position), self.bytes.get(self.position + 1)) {
+                (Some(b' ' | b'\t' | b'\r' | b'\n'), _) => self.position += 1,
+                (Some(b'/'), Some(b'/')) => {
+                    self.position = self.text[self.position..].find('\n')
+                        .map_or(self.bytes.len(), |newline_position| self.position + newline_position + 1);
+                },
+                (Some(b'/'), Some(b'*')) => {
+                    let comment_end = self.text[self.position + 2..].find("*/")
+                        .ok_or_else(|| self.error("Unterminated comment"))?;
+                    self.position += 2 + comment_end + 2;
+                },
+                _ => return Ok(())
+            }
+        }
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        let position = self.position.min(self.bytes.len());
+        let line = self.text[..position].matches('\n').count() + 1;
+
+        anyhow::anyhow!("{} at line {}!", message, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES_LIST: &[&str] = &["profiles", "list"];
+
+    const SETTINGS: &str = r#"// This file was initially generated by Windows Terminal
+{
+    "$schema": "https://aka.ms/terminal-profiles-schema",
+    "defaultProfile": "{61c54bbd-c2c6-5271-96e7-009a87ff44bf}",
+    /* keep the profiles sorted */
+    "profiles":
+    {
+        "defaults": {},
+        "list":
+        [
+            {
+                // Make changes here to the powershell.exe profile.
+                "guid": "{61c54bbd-c2c6-5271-96e7-009a87ff44bf}",
+                "name": "Windows PowerShell",
+                "hidden": false,
+            },
+            {
+                "guid": "{0caa0dad-35be-5f56-a8ff-afceeeaa6101}",
+                "name": "Command Prompt", // cmd
+                "hidden": false
+            }
+        ]
+    },
+    "schemes": [],
+}
+"#;
+
+    fn profile(guid: &str) -> serde_json::Value {
+        serde_json::json!({ "guid": guid, "name": "dev", "commandline": "dragon run -w dev" })
+    }
+
+    #[test]
+    fn reads_array_elements_with_comments_and_trailing_commas() {
+        let document = JsoncDocument::parse(SETTINGS.to_string()).unwrap();
+        let profiles = document.array_elements(PROFILES_LIST).unwrap();
+
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[1]["name"], "Command Prompt");
+        assert!(document.array_elements(&["schemes"]).unwrap().is_empty());
+        assert!(document.array_elements(&["profiles", "defaults"]).is_err());
+        assert!(document.array_elements(&["actions"]).is_err());
+    }
+
+    #[test]
+    fn prepends_element_leaving_the_rest_intact() {
+        let mut document = JsoncDocument::parse(SETTINGS.to_string()).unwrap();
+        document.prepend_array_element(PROFILES_LIST, &profile("{1}")).unwrap();
+
+        let expected_insertion = concat!(
+            "            {\n",
+            "                \"guid\": \"{1}\",\n",
+            "                \"name\": \"dev\",\n",
+            "                \"commandline\": \"dragon run -w dev\"\n",
+            "            },\n"
+        );
+        let insertion_position = SETTINGS.find("            {\n                // Make").unwrap();
+        let expected_text = format!("{}{}{}", &SETTINGS[..insertion_position], expected_insertion, &SETTINGS[insertion_position..]);
+
+        assert_eq!(document.text(), expected_text);
+    }
+
+    #[test]
+    fn prepends_element_to_empty_array() {
+        let mut document = JsoncDocument::parse("{\r\n  \"profiles\": { \"list\": [] } // none yet\r\n}\r\n".to_string()).unwrap();
+        document.prepend_array_element(PROFILES_LIST, &serde_json::json!({ "guid": "{1}" })).unwrap();
+
+        assert_eq!(document.text(), "{\r\n  \"profiles\": { \"list\": [\r\n      {\r\n          \"guid\": \"{1}\"\r\n      }\r\n  ] } // none yet\r\n}\r\n");
+    }
+
+    #[test]
+    fn replaces_element_in_place() {
+        let mut document = JsoncDocument::parse(SETTINGS.to_string()).unwrap();
+        document.replace_array_element(PROFILES_LIST, 1, &serde_json::json!({ "guid": "{2}" })).unwrap();
+
+        let old_element_start = SETTINGS.find("{\n                \"guid\": \"{0caa").unwrap();
+        let old_element_end = SETTINGS[old_element_start..].find("\n            }").unwrap() + old_element_start + "\n            }".len();
+        let expected_text = format!("{}{{\n                \"guid\": \"{{2}}\"\n            }}{}", &SETTINGS[..old_element_start], &SETTINGS[old_element_end..]);
+
+        assert_eq!(document.text(), expected_text);
+        assert!(document.replace_array_element(PROFILES_LIST, 2, &profile("{3}")).is_err());
+    }
+
+    #[test]
+    fn removes_elements_with_their_commas() {
+        let mut document = JsoncDocument::parse(SETTINGS.to_string()).unwrap();
+
+        document.remove_array_element(PROFILES_LIST, 1).unwrap();
+        let last_element_start = SETTINGS.find("            {\n                \"guid\": \"{0caa").unwrap();
+        let last_element_end = SETTINGS.find("        ]\n").unwrap();
+        let expected_text = format!("{}\n{}", &SETTINGS[..last_element_start - ",\n".len()], &SETTINGS[last_element_end..]);
+        assert_eq!(document.text(), expected_text);
+
+        document.remove_array_element(PROFILES_LIST, 0).unwrap();
+        assert!(document.text().contains("        \"list\":\n        [\n        ]\n"));
+        assert!(document.text().starts_with("// This file was initially generated by Windows Terminal\n"));
+        assert!(document.array_elements(PROFILES_LIST).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        assert!(JsoncDocument::parse("{ \"a\": }".to_string()).is_err());
+        assert!(JsoncDocument::parse("{ \"a\": 1 /* unterminated".to_string()).is_err());
+        assert!(JsoncDocument::parse("[1, 2] 3".to_string()).is_err());
+        assert!(JsoncDocument::parse("{ \"a\": tru }".to_string()).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde::{Serialize, Deserialize};
+
+use crate::dry_run;
+use crate::safe_file;
+
+const LOCK_FILE_HEADER: &str = "# Written by dragon. Commit it to share the exact images, install them with `dragon upgrade --locked`.\n";
+
+/// The `.dockerwsl.lock` file, pinning the image of each WSL to the manifest digest it resolved to.
+///
+/// Tags are mutable, the digest is not, so `dragon upgrade --locked` installs the same image on every machine.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LockFile {
+    #[serde(default)]
+    pub wsls: Vec<LockedImage>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedImage {
+    /// Name of the WSL in .dockerwsl.
+    pub name: String,
+    /// The image with its tag, like `acr.io/team/dev:1.2`.
+    pub image: String,
+    /// Manifest digest the tag pointed to, like `sha256:<hex>`.
+    pub digest: String
+}
+
+impl LockFile {
+    /// The lock file belonging to a .dockerwsl file, `.dockerwsl.lock` next to it.
+    pub fn default_path(dockerwsl_path: &Path) -> PathBuf {
+        let mut lock_file_name = dockerwsl_path.file_name().map(|file_name| file_name.to_os_string()).unwrap_or_default();
+        lock_file_name.push(".lock");
+
+        dockerwsl_path.with_file_name(lock_file_name)
+    }
+
+    /// Reads the lock file, a lock file that doesn't exist yet is empty.
+    pub fn read(lock_file_path: &Path) -> Result<LockFile> {
+        if !lock_file_path.exists() {
+            return Ok(LockFile::default());
+        }
+
+        let file = File::open(lock_file_path)
+            .with_context(|| format!("Could not open lock file `{}` for reading!", lock_file_path.display()))?;
+        let lock_file: Option<LockFile> = serde_yaml::from_reader(BufReader::new(file))
+            .with_context(|| format!("Could not parse lock file `{}`!", lock_file_path.display()))?;
+
+        Ok(lock_file.unwrap_or_default())
+    }
+
+    pub fn write(&self, lock_file_path: &Path) -> Result<()> {
+        let lock_file_content = format!("{}{}", LOCK_FILE_HEADER, serde_yaml::to_string(self)
+            .with_context(|| "Could not serialize the lock file!")?);
+
+        if dry_run::is_enabled() {
+            dry_run::print_file_change(lock_file_path, &lock_file_content);
+            return Ok(());
+        }
+
+        safe_file::write(lock_file_path, lock_file_content.as_bytes())
+            .with_context(|| format!("An error happened while writing lock file `{}`!", lock_file_path.display()))
+    }
+
+    pub fn get(&self, wsl_name: &str) -> Option<&LockedImage> {
+        self.wsls.iter().find(|locked_image| locked_image.name == wsl_name)
+    }
+
+    /// Pins a WSL to an image digest, replacing what it was pinned to before. Entries are kept sorted by name so the file diffs well.
+    pub fn lock(&mut self, wsl_name: &str, image_url_str: &str, digest: &str) {
+        self.unlock(wsl_name);
+        self.wsls.push(LockedImage { name: wsl_name.to_string(), image: image_url_str.to_string(), digest: digest.to_string() });
+        self.wsls.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn unlock(&mut self, wsl_name: &str) {
+        self.wsls.retain(|locked_image| locked_image.name != wsl_name);
+    }
+}
+
+/// Set once from `--lock-file`, `None` when the lock file is next to .dockerwsl.
+static LOCK_FILE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn set_path(lock_file_path: Option<PathBuf>) {
+    *LOCK_FILE_PATH.lock().unwrap() = lock_file_path;
+}
+
+/// The lock file every command pins to and reads from: the one passed with `--lock-file`, or the one next to .dockerwsl.
+pub fn path(dockerwsl_path: &Path) -> PathBuf {
+    LOCK_FILE_PATH.lock().unwrap().clone().unwrap_or_else(|| LockFile::default_path(dockerwsl_path))
+}
+
+/// Pins `wsl_name` in the lock file at `lock_file_path`. Without a digest the WSL is unpinned instead, so the lock file
+/// never keeps the digest of an image the WSL has moved away from.
+pub fn update_lock_file(lock_file_path: &Path, wsl_name: &str, image_url_str: &str, digest: Option<&str>) -> Result<()> {
+    match digest {
+        Some(digest) => pin_images(lock_file_path, &[LockedImage { name: wsl_name.to_string(), image: image_url_str.to_string(), digest: digest.to_string() }], &[]),
+        None => {
+            message!("Could not determine the digest of image `{}`, WSL `{}` is not pinned in `{}`!", image_url_str, wsl_name, lock_file_path.display());
+            pin_images(lock_file_path, &[], &[wsl_name])
+        }
+    }
+}
+
+/// Pins the images of several WSLs and unpins `unpinned_wsl_names` at once. The lock file is read again while holding
+/// its lock, so what other dragon processes pinned in the meantime is kept.
+pub fn pin_images(lock_file_path: &Path, locked_images: &[LockedImage], unpinned_wsl_names: &[&str]) -> Result<()> {
+    change_lock_file(lock_file_path, |lock_file| {
+        for locked_image in locked_images.iter() {
+            lock_file.lock(&locked_image.name, &locked_image.image, &locked_image.digest);
+        }
+        for wsl_name in unpinned_wsl_names.iter() {
+            lock_file.unlock(wsl_name);
+        }
+    })
+}
+
+/// Drops `wsl_name` from the lock file at `lock_file_path`, if there is one.
+pub fn remove_from_lock_file(lock_file_path: &Path, wsl_name: &str) -> Result<()> {
+    pin_images(lock_file_path, &[], &[wsl_name])
+}
+
+/// Reads the lock file while holding its lock and writes it back if `change` changed it. A lock file without any pinned
+/// image left is deleted.
+fn change_lock_file(lock_file_path: &Path, change: impl FnOnce(&mut LockFile)) -> Result<()> {
+    let _file_lock = safe_file::lock(lock_file_path)?;
+    let mut lock_file = LockFile::read(lock_file_path)?;
+    let previous_lock_file = lock_file.clone();
+
+    change(&mut lock_file);
+    if lock_file == previous_lock_file {
+        return Ok(());
+    }
+
+    if lock_file.wsls.is_empty() {
+        if dry_run::is_enabled() {
+            message!("Would delete lock file `{}`, there are no pinned images left.", lock_file_path.display());
+            return Ok(());
+        }
+
+        return fs::remove_file(lock_file_path)
+            .with_context(|| format!("Could not delete the empty lock file `{}`!", lock_file_path.display()));
+    }
+
+    lock_file.write(lock_file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST_A: &str = "sha256:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const DIGEST_B: &str = "sha256:bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    #[test]
+    fn places_lock_file_next_to_dockerwsl() {
+        assert_eq!(LockFile::default_path(Path::new("/home/dev/.dockerwsl")), PathBuf::from("/home/dev/.dockerwsl.lock"));
+        assert_eq!(LockFile::default_path(Path::new("team.yaml")), PathBuf::from("team.yaml.lock"));
+    }
+
+    #[test]
+    fn locks_replaces_and_removes_entries() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let lock_file_path = temp_dir.path().join(".dockerwsl.lock");
+
+        update_lock_file(&lock_file_path, "web", "acr.io/team/web:1.0", Some(DIGEST_A)).unwrap();
+        update_lock_file(&lock_file_path, "dev", "alexchiri/dev:1", Some(DIGEST_A)).unwrap();
+        update_lock_file(&lock_file_path, "dev", "alexchiri/dev:2", Some(DIGEST_B)).unwrap();
+
+        let content = fs::read_to_string(&lock_file_path).unwrap();
+        assert!(content.starts_with(LOCK_FILE_HEADER));
+
+        let lock_file = LockFile::read(&lock_file_path).unwrap();
+        assert_eq!(lock_file.wsls, vec![
+            LockedImage { name: "dev".to_string(), image: "alexchiri/dev:2".to_string(), digest: DIGEST_B.to_string() },
+            LockedImage { name: "web".to_string(), image: "acr.io/team/web:1.0".to_string(), digest: DIGEST_A.to_string() }
+        ]);
+
+        // a tag without a known digest must not stay pinned to the digest of the previous tag
+        update_lock_file(&lock_file_path, "web", "acr.io/team/web:1.1", None).unwrap();
+        assert_eq!(LockFile::read(&lock_file_path).unwrap().get("web"), None);
+
+        remove_from_lock_file(&lock_file_path, "dev").unwrap();
+        remove_from_lock_file(&lock_file_path, "web").unwrap();
+        assert!(!lock_file_path.exists());
+        assert_eq!(LockFile::read(&lock_file_path).unwrap(), LockFile::default());
+    }
+}
@@ -1,18 +1,51 @@
+#[macro_use]
+mod output;
+
+mod config_edit;
+mod credentials;
+mod dry_run;
+mod image;
+mod jobs;
+mod jsonc;
+mod lockfile;
+mod profile_template;
+mod progress;
+mod reference;
+mod registry;
+mod runtime;
+mod safe_file;
+mod schema;
+mod tag_policy;
+mod windows_terminal;
+
 use std::path::{PathBuf, Path};
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{BufReader, BufWriter};
+use std::io::IsTerminal;
+use std::fs::create_dir_all;
 use std::process::Command;
+use std::sync::Mutex;
 
 use structopt::StructOpt;
 use anyhow::{Context, Result};
 use log::debug;
 use simple_logger::SimpleLogger;
 use serde::{Serialize, Deserialize};
-use json_comments::StripComments;
 use tempfile::{Builder, TempDir};
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 
+use credentials::{Secret, SecretRef};
+use image::ImageArchive;
+use jsonc::JsoncDocument;
+use lockfile::{LockFile, LockedImage};
+use output::{OutputFormat, VmStatus, WslResult};
+use profile_template::ProfileTemplate;
+use progress::Progress;
+use reference::ImageReference;
+use registry::{RegistryClient, RegistryCredentials};
+use runtime::{ContainerRuntime, Runtime};
+use tag_policy::TagPolicy;
+use windows_terminal::{WindowsTerminalInstall, WindowsTerminalProfileState};
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "dragon", about = "A CLI tool that manages Docker generated WSL2 VMs and Windows Terminal profiles.")]
 struct Dragon {
@@ -21,7 +54,20 @@ struct Dragon {
 
     /// Control verbosity of the output. Valid options -v, -vv, -vvv.
     #[structopt(flatten)]
-    verbose: clap_verbosity_flag::Verbosity
+    verbose: clap_verbosity_flag::Verbosity,
+
+    /// Print what would be pulled, imported, unregistered, deleted and written, without doing any of it.
+    #[structopt(long = "dry-run", global = true)]
+    dry_run: bool,
+
+    /// How results are printed: text, or json for a single JSON report on stdout with a result per WSL.
+    #[structopt(long, global = true, default_value = "text")]
+    output: OutputFormat,
+
+    /// Path to the lock file pinning the image digests, used by every command that pins or reads them.
+    /// Optional, if not provided, `.dockerwsl.lock` next to the .dockerwsl file is used.
+    #[structopt(long = "lock-file", global = true, parse(from_os_str), env="DOCKERWSL_LOCK_PATH")]
+    lock_file: Option<PathBuf>
 }
 
 #[derive(Debug, StructOpt)]
@@ -31,37 +77,169 @@ enum SubCommand {
     /// Creates new WSL VM with the latest version (if doesn't exist) and updates .dockerwsl image url to contain the latest tag.
     Upgrade(Upgrade),
     /// Creates .dockerwsl entry from image url, pulls image, creates WSL VM and creates record in Windows Terminal settings.json file.
-    New(New),
-    /// Only for ACR based images. Determines the latest tag for a repository and updates the latest property in .dockerwsl file
+    New(Box<New>),
+    /// Determines the latest tag for a repository using the registry API and updates the latest property in .dockerwsl file
     Update(Update),
     /// Runs a configured and existing WSL VM by name.
-    Run(Run)
+    Run(Run),
+    /// Removes a WSL from .dockerwsl, unregisters all its WSL VMs, deletes their folders and its Windows Terminal profile.
+    Remove(Remove),
+    /// Unregisters all but the newest WSL VMs of a WSL and deletes their folders. The WSL VM currently in use is always kept.
+    Cleanup(Cleanup),
+    /// Shows the WSLs configured in .dockerwsl next to the installed WSL VMs and Windows Terminal profiles and flags any drift between them.
+    #[structopt(visible_alias = "list")]
+    Status(Status),
+    /// Points a WSL back at a previously installed tag, re-importing its WSL VM if it has been cleaned up.
+    Rollback(Rollback),
+    /// Manages the .dockerwsl file itself.
+    Config(Config)
 
     // Test(Test)
 }
 
+impl SubCommand {
+    /// The name of the subcommand, as used in the JSON report.
+    fn name(&self) -> &'static str {
+        match self {
+            SubCommand::Pull(_) => "pull",
+            SubCommand::Upgrade(_) => "upgrade",
+            SubCommand::New(_) => "new",
+            SubCommand::Update(_) => "update",
+            SubCommand::Run(_) => "run",
+            SubCommand::Remove(_) => "remove",
+            SubCommand::Cleanup(_) => "cleanup",
+            SubCommand::Status(_) => "status",
+            SubCommand::Rollback(_) => "rollback",
+            SubCommand::Config(config) => config.command.name()
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct Config {
+    #[structopt(subcommand)]
+    command: ConfigCommand
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    /// Upgrades .dockerwsl to the current schema version and shows what changed. Older files are also upgraded by any command writing .dockerwsl.
+    Migrate(ConfigMigrate),
+    /// Checks .dockerwsl for yaml errors, values of the wrong type, duplicate WSL names, invalid image references and profile GUIDs,
+    /// private registries without credentials and install paths that don't exist.
+    Validate(ConfigValidate),
+    /// Prints .dockerwsl as dragon reads it, with the passwords written in it redacted.
+    Show(ConfigShow),
+    /// Sets a value in .dockerwsl, e.g. `dragon config set wsls.dev.keep_versions 3`. The change is only written if .dockerwsl is still valid with it.
+    Set(ConfigSet),
+    /// Opens .dockerwsl in $EDITOR and validates it when the editor is closed. It is only written if it is valid.
+    Edit(ConfigEdit)
+}
+
+impl ConfigCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ConfigCommand::Migrate(_) => "config migrate",
+            ConfigCommand::Validate(_) => "config validate",
+            ConfigCommand::Show(_) => "config show",
+            ConfigCommand::Set(_) => "config set",
+            ConfigCommand::Edit(_) => "config edit"
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ConfigMigrate {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ConfigValidate {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ConfigShow {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ConfigSet {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+
+    /// Keys separated by dots, items of a list are picked by name or position, e.g. `wsls.dev.latest` or `private_registries.0.tenant`.
+    path: String,
+
+    /// The new value, read as yaml, e.g. `3`, `null` or `{ env: ACR_PASSWORD }`.
+    value: String
+}
+
+#[derive(Debug, StructOpt)]
+struct ConfigEdit {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+}
+
 #[derive(Debug, StructOpt)]
 struct Upgrade {
     /// Path to the .dockerwsl file. Mandatory.
     #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
     dockerwsl: PathBuf,
-    /// Path to the Windows Terminal configuration file. Mandatory.
+    /// Path to the Windows Terminal configuration file.
+    /// Optional, if not provided, the settings.json files of the Windows Terminal installs found on this machine are used.
     #[structopt(short = "t", long, parse(from_os_str), env="WT_SETTINGS_PATH")]
-    wtconfig: PathBuf,
+    wtconfig: Option<PathBuf>,
+    /// Don't create or update a Windows Terminal profile, whatever `windows_terminal_profile_backend` in .dockerwsl says.
+    #[structopt(long = "no-terminal-profile")]
+    no_terminal_profile: bool,
+    /// Install exactly the image digests pinned in the lock file, instead of the `latest` tags.
+    #[structopt(long)]
+    locked: bool,
+    /// How many WSLs are upgraded at the same time, their messages are prefixed with the WSL name.
+    /// Optional, if not provided, one WSL at a time.
+    #[structopt(short = "j", long, default_value = "1")]
+    jobs: usize,
+    /// Stop at the first WSL that fails, instead of going on with the others and failing at the end.
+    #[structopt(long = "fail-fast")]
+    fail_fast: bool,
     /// Which WSL VM would you like to change? Provide its name as configured in .dockerwsl.
     /// Optional, if not provided, all WSLs are gonna be affected. 
     #[structopt(short = "w", long)]
     wsl: Option<String>,
+    /// How images are pulled and exported: builtin, docker, podman or nerdctl.
+    /// Optional, if not provided, `runtime` from .dockerwsl is used, or builtin if that's not set either.
+    #[structopt(long)]
+    runtime: Option<Runtime>,
 }
 #[derive(Debug, StructOpt)]
 struct Pull {
     /// Path to the .dockerwsl file. Mandatory.
     #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
     dockerwsl: PathBuf,
+    /// How many WSLs are pulled at the same time, their messages are prefixed with the WSL name.
+    /// Optional, if not provided, one WSL at a time.
+    #[structopt(short = "j", long, default_value = "1")]
+    jobs: usize,
+    /// Stop at the first WSL that fails, instead of going on with the others and failing at the end.
+    #[structopt(long = "fail-fast")]
+    fail_fast: bool,
     /// Which WSL VM would you like to change? Provide its name as configured in .dockerwsl.
     /// Optional, if not provided, all WSLs are gonna be affected. 
     #[structopt(short = "w", long)]
     wsl: Option<String>,
+    /// How images are pulled and exported: builtin, docker, podman or nerdctl.
+    /// Optional, if not provided, `runtime` from .dockerwsl is used, or builtin if that's not set either.
+    #[structopt(long)]
+    runtime: Option<Runtime>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -69,12 +247,21 @@ struct New {
     /// Path to the .dockerwsl file. Mandatory.
     #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
     dockerwsl: PathBuf,
-    /// Path to the Windows Terminal configuration file. Mandatory.
+    /// Path to the Windows Terminal configuration file.
+    /// Optional, if not provided, the settings.json files of the Windows Terminal installs found on this machine are used.
     #[structopt(short = "s", long, parse(from_os_str), env="WT_SETTINGS_PATH")]
-    wtconfig: PathBuf,
+    wtconfig: Option<PathBuf>,
+    /// Don't create or update a Windows Terminal profile, whatever `windows_terminal_profile_backend` in .dockerwsl says.
+    #[structopt(long = "no-terminal-profile")]
+    no_terminal_profile: bool,
     /// Image URL, can be given as registry/repository:tag, or registry/repository or just repository[:tag] if using image from Docker Hub
-    #[structopt(short = "i", long)]
-    image: String,
+    /// Optional only with `--from-archive`, if not provided, the image name found in the archive is used.
+    #[structopt(short = "i", long, required_unless = "from-archive")]
+    image: Option<String>,
+    /// Path to a `docker save` tarball or an OCI image layout (directory or tar) to create the WSL VM from, instead of pulling the image from its registry.
+    /// Optional, the WSL keeps using the archive on `dragon upgrade`.
+    #[structopt(long = "from-archive", parse(from_os_str))]
+    from_archive: Option<PathBuf>,
     /// The name of the WSL that is used in the VM name and in the .dockerwsl config.
     /// Optional, if not provided, repository name will be used
     #[structopt(short = "w", long)]
@@ -87,11 +274,25 @@ struct New {
     #[structopt(short = "u", long)]
     username: Option<String>,
     /// Password to be used for private registry. Optional
-    #[structopt(short = "p", long)]
-    password: Option<String>,
+    /// It ends up as is in .dockerwsl, prefer `--password-env`, `--password-file` or `--credential-helper`.
+    #[structopt(short = "p", long, conflicts_with_all = &["password-env", "password-file", "credential-helper"])]
+    password: Option<Secret>,
+    /// Environment variable holding the password of the private registry, only its name is kept in .dockerwsl. Optional
+    #[structopt(long = "password-env", conflicts_with_all = &["password-file", "credential-helper"])]
+    password_env: Option<String>,
+    /// File holding the password of the private registry, only its path is kept in .dockerwsl. Optional
+    #[structopt(long = "password-file", conflicts_with = "credential-helper")]
+    password_file: Option<String>,
+    /// docker-credential-helpers helper to get the private registry credentials from, like `wincred` or `pass`, instead of `--username` and a password. Optional
+    #[structopt(long = "credential-helper", conflicts_with = "username")]
+    credential_helper: Option<String>,
     /// Tenant to be used for ACR registries only. Optional
     #[structopt(short = "t", long)]
-    tenant: Option<String>
+    tenant: Option<String>,
+    /// How images are pulled and exported: builtin, docker, podman or nerdctl.
+    /// Optional, if not provided, `runtime` from .dockerwsl is used, or builtin if that's not set either.
+    #[structopt(long)]
+    runtime: Option<Runtime>
 }
 
 #[derive(Debug, StructOpt)]
@@ -99,11 +300,10 @@ struct Update {
     /// Path to the .dockerwsl file. Mandatory.
     #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
     dockerwsl: PathBuf,
+    /// Stop at the first WSL that fails, instead of going on with the others and failing at the end.
+    #[structopt(long = "fail-fast")]
+    fail_fast: bool,
 
-    /// Path to the az CLI. Mandatory.
-    #[structopt(short = "a", long, parse(from_os_str), env="AZ_CLI_PATH")]
-    az_cli: PathBuf,
-    
     /// Which WSL VM would you like to change? Provide its name as configured in .dockerwsl.
     /// Optional, if not provided, all WSLs are gonna be affected. 
     #[structopt(short = "w", long)]
@@ -125,27 +325,213 @@ struct Run {
     wsl: String,
 }
 
+#[derive(Debug, StructOpt)]
+struct Remove {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+    /// Path to the Windows Terminal configuration file.
+    /// Optional, if not provided, the settings.json files of the Windows Terminal installs found on this machine are used.
+    #[structopt(short = "t", long, parse(from_os_str), env="WT_SETTINGS_PATH")]
+    wtconfig: Option<PathBuf>,
+    /// Which WSL would you like to remove? Provide its name as configured in .dockerwsl. Mandatory.
+    #[structopt(short = "w", long)]
+    wsl: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct Cleanup {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+    /// Which WSL would you like to clean up? Provide its name as configured in .dockerwsl.
+    /// Optional, if not provided, all WSLs are gonna be affected.
+    #[structopt(short = "w", long)]
+    wsl: Option<String>,
+    /// How many of the newest WSL VMs to keep for each WSL.
+    /// Optional, overrides `keep_versions` from .dockerwsl. If not configured anywhere, only the newest one is kept.
+    #[structopt(short = "k", long)]
+    keep: Option<usize>,
+}
+
+#[derive(Debug, StructOpt)]
+struct Status {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+    /// Path to the Windows Terminal configuration file.
+    /// Optional, if not provided, Windows Terminal profiles are not checked.
+    #[structopt(short = "t", long, parse(from_os_str), env="WT_SETTINGS_PATH")]
+    wtconfig: Option<PathBuf>,
+    /// Which WSL would you like to see? Provide its name as configured in .dockerwsl.
+    /// Optional, if not provided, all WSLs are shown.
+    #[structopt(short = "w", long)]
+    wsl: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct Rollback {
+    /// Path to the .dockerwsl file. Mandatory.
+    #[structopt(short = "c", long, parse(from_os_str), env="DOCKERWSL_PATH")]
+    dockerwsl: PathBuf,
+    /// Which WSL would you like to roll back? Provide its name as configured in .dockerwsl. Mandatory.
+    #[structopt(short = "w", long)]
+    wsl: String,
+    /// The tag to roll back to, see `history` in .dockerwsl for the installed ones.
+    /// Optional, if not provided, the most recently installed tag other than the current one is used.
+    #[structopt(long)]
+    to: Option<String>,
+    /// How images are pulled and exported if the WSL VM has to be re-imported: builtin, docker, podman or nerdctl.
+    /// Optional, if not provided, `runtime` from .dockerwsl is used, or builtin if that's not set either.
+    #[structopt(long)]
+    runtime: Option<Runtime>,
+}
+
+#[derive(Debug)]
+struct WSLVMStatus {
+    name: String,
+    state: String,
+    version: String
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct DockerWSLConf {
+    /// Schema version of the file, older files are migrated when they are read, see `schema`.
+    version: u64,
+    #[serde(default)]
     wsls: Vec<WSLConf>,
     default_base_wsl_install_path: Option<String>,
+    /// Default number of WSL VMs kept per WSL by `dragon cleanup`.
+    default_keep_versions: Option<usize>,
+    /// How images are pulled and turned into WSL VM tars. Optional, if not provided, `builtin` is used.
+    runtime: Option<Runtime>,
+    /// Where the Windows Terminal profiles are written. Optional, if not provided, `settings` is used.
+    windows_terminal_profile_backend: Option<ProfileBackend>,
+    /// Which Windows Terminal installs get the profiles when no `--wtconfig` is passed. Optional, if not provided, all that are found.
+    windows_terminal_installs: Option<Vec<WindowsTerminalInstall>>,
+    /// Windows Terminal profile settings applied to the profiles of all WSLs.
+    default_windows_terminal_profile: Option<ProfileTemplate>,
+    #[serde(default)]
     private_registries: Vec<Registry>
 }
 
+/// Where the Windows Terminal profiles of the WSLs are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ProfileBackend {
+    /// `profiles.list` in the Windows Terminal settings.json file.
+    #[default]
+    Settings,
+    /// A JSON fragment extension owned by dragon, regenerated from .dockerwsl. settings.json is never touched.
+    Fragment,
+    /// No Windows Terminal profiles at all.
+    Disabled
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Registry {
     name: String,
-    username: String,
-    password: String,
+    /// Not needed with a `credential_helper`, which returns the username too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<SecretRef>,
+    /// docker-credential-helpers helper, like `wincred`, to get the username and password from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credential_helper: Option<String>,
     tenant: Option<String>
 }
-#[derive(Debug, Serialize, Deserialize)]
+
+impl Registry {
+    /// Resolves the secret references, so this is the only place the password is read.
+    fn credentials(&self) -> Result<RegistryCredentials> {
+        let (username, password) = match &self.credential_helper {
+            Some(credential_helper) => credentials::get_from_credential_helper(credential_helper, &self.name)?,
+            None => {
+                let username = self.username.clone()
+                    .with_context(|| format!("There is no username or credential_helper for private registry `{}` in .dockerwsl!", &self.name))?;
+                let password = self.password.as_ref()
+                    .with_context(|| format!("There is no password or credential_helper for private registry `{}` in .dockerwsl!", &self.name))?
+                    .resolve()
+                    .with_context(|| format!("Could not get the password of private registry `{}`!", &self.name))?;
+
+                (username, password)
+            }
+        };
+
+        Ok(RegistryCredentials {
+            username,
+            password: password.expose().to_string(),
+            tenant: self.tenant.clone()
+        })
+    }
+
+    /// Checks the credentials are all there, without running the credential helper. Passwords in environment variables
+    /// and files that can't be read only get a warning, they may be set up on other machines only.
+    fn check_credentials(&self, config_problems: &mut ConfigProblems) {
+        if self.credential_helper.is_some() {
+            return;
+        }
+
+        if self.username.is_none() {
+            config_problems.errors.push(format!("Private registry `{}` has no username or credential_helper!", &self.name));
+        }
+
+        match &self.password {
+            None => config_problems.errors.push(format!("Private registry `{}` has no password or credential_helper!", &self.name)),
+            Some(SecretRef::Plain(_)) => {},
+            Some(password) => {
+                if let Err(error) = password.resolve() {
+                    config_problems.warnings.push(format!("The password of private registry `{}` can't be read: {:#}", &self.name, error));
+                }
+            }
+        }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct WSLConf {
     name: String,
     image: String,
     latest: Option<String>,
     windows_terminal_profile_id: String,
-    base_install_path: String
+    base_install_path: String,
+    /// Path to a `docker save` tarball or an OCI image layout the WSL VMs are created from, instead of the registry.
+    archive: Option<String>,
+    /// How `dragon update` picks the latest tag. Optional, if not provided, the newest tag is picked.
+    tag_policy: Option<TagPolicy>,
+    /// Number of WSL VMs kept for this WSL by `dragon cleanup`, overrides `default_keep_versions`.
+    keep_versions: Option<usize>,
+    /// Windows Terminal profile settings of this WSL, applied on top of `default_windows_terminal_profile`.
+    windows_terminal_profile: Option<ProfileTemplate>,
+    /// The settings.json files that got a profile for this WSL. Profiles in the fragment are not tracked, dragon owns it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    windows_terminal_profiles: Vec<WindowsTerminalProfileState>,
+    /// Every tag installed by `dragon new`, `dragon upgrade` or `dragon rollback`, oldest first. Used by `dragon rollback`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<InstalledVersion>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstalledVersion {
+    tag: String,
+    image: String,
+    /// Manifest digest of the image, if it could be determined.
+    digest: Option<String>,
+    /// RFC 3339 timestamp of the install.
+    installed_at: String,
+    install_path: String
+}
+
+impl InstalledVersion {
+    fn new(image_url_str: &str, tag: &str, digest: Option<String>, install_path: &Path) -> InstalledVersion {
+        InstalledVersion {
+            tag: tag.to_string(),
+            image: image_url_str.to_string(),
+            digest,
+            installed_at: chrono::Utc::now().to_rfc3339(),
+            install_path: install_path.to_string_lossy().into_owned()
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -155,38 +541,73 @@ fn main() -> Result<()> {
     
     debug!("{:#?}", dragon_params);
 
-    match dragon_params.command {
+    output::set_format(dragon_params.output);
+    lockfile::set_path(dragon_params.lock_file.clone());
+    if dragon_params.dry_run {
+        dry_run::enable();
+        message!("Dry run, nothing will be changed.");
+    }
+
+    let command_name = dragon_params.command.name();
+    let command_result = match dragon_params.command {
         SubCommand::Pull(pull_command) => {
             debug!("Received a Pull command: {:#?}", pull_command);
-            return handle_pull(pull_command);
+            handle_pull(pull_command)
         }
 
         SubCommand::Upgrade(upgrade_command) => {
             debug!("Received an Upgrade command: {:#?}", upgrade_command);
-            return handle_upgrade(upgrade_command);
+            handle_upgrade(upgrade_command)
         }
 
         SubCommand::New(new_command) => {
             debug!("Received a New command: {:#?}", new_command);
-            return handle_new(new_command);
+            handle_new(*new_command)
         }
 
         SubCommand::Run(run_command) => {
             debug!("Received a Run command: {:#?}", run_command);
-            return handle_run(run_command);
+            handle_run(run_command)
         }
 
         SubCommand::Update(update_command) => {
             debug!("Received an Update command: {:#?}", update_command);
-            return handle_update(update_command);
+            handle_update(update_command)
+        }
+
+        SubCommand::Remove(remove_command) => {
+            debug!("Received a Remove command: {:#?}", remove_command);
+            handle_remove(remove_command)
+        }
+
+        SubCommand::Cleanup(cleanup_command) => {
+            debug!("Received a Cleanup command: {:#?}", cleanup_command);
+            handle_cleanup(cleanup_command)
+        }
+
+        SubCommand::Status(status_command) => {
+            debug!("Received a Status command: {:#?}", status_command);
+            handle_status(status_command)
+        }
+
+        SubCommand::Rollback(rollback_command) => {
+            debug!("Received a Rollback command: {:#?}", rollback_command);
+            handle_rollback(rollback_command)
+        }
+        SubCommand::Config(config_command) => {
+            debug!("Received a Config command: {:#?}", config_command);
+            handle_config(config_command)
         }
 
         // SubCommand::Test(test_command) => {
         //     debug!("Received a Test command: {:#?}", test_command);
         //     return handle_test(test_command);
         // }
-    }
+    };
+
+    output::print_report(command_name, dragon_params.dry_run, &command_result);
 
+    command_result
 }
 
 // fn handle_test(test: Test) -> Result<()> {    
@@ -196,7 +617,7 @@ fn main() -> Result<()> {
 //     let wsl_run_command_status = wsl_run_command.output()
 //         .with_context(|| format!("`az.cmd version` failed!"))?;
 
-//     println!("{:#?}", wsl_run_command_status);
+//     message!("{:#?}", wsl_run_command_status);
     
 //     Ok(())
 // }
@@ -207,6 +628,9 @@ fn handle_update(update: Update) -> Result<()> {
     
     let mut dockerwsl_content = parse_dockerwslconf_file(&dockerwsl_path)
         .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
+    let fail_fast = update.fail_fast || wsl_name.is_some();
+    let mut fail_fast_error = None;
+    let mut updated_wsl_names = vec![];
 
     for wsl_conf in dockerwsl_content.wsls.iter_mut() {
         match wsl_name {
@@ -221,51 +645,81 @@ fn handle_update(update: Update) -> Result<()> {
             None => { debug!("No wsl name passed to `upgrade`, will upgrade all wsls in the config file!"); }
         }
 
-        let image_url_str = wsl_conf.image.as_str();
+        if wsl_conf.archive.is_some() {
+            message!("WSL `{}` is created from an image archive, set its latest property manually!", &wsl_conf.name);
+            continue;
+        }
 
-        let (registry_name_option, repository_name, _tag) = extract_generic_image_details(image_url_str)
-            .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url_str))?;
+        match update_wsl_conf(wsl_conf, &dockerwsl_content.private_registries) {
+            Ok(wsl_result) => {
+                updated_wsl_names.push(wsl_conf.name.clone());
+                output::record(wsl_result);
+            },
+            Err(error) => {
+                output::record(WslResult::failed(&wsl_conf.name, &error));
+                if fail_fast {
+                    fail_fast_error = Some(error);
+                    break;
+                }
+                message!("Could not update WSL `{}`, going on with the next one: {:#}", &wsl_conf.name, error);
+            }
+        }
+    }
 
-        if registry_name_option.is_none() {
-            return Err(anyhow::anyhow!("`dragon update` is only supported for ACR images!"));
-        } else {
-            let registry_name = registry_name_option.unwrap();
-            
-            if !registry_name.ends_with(".azurecr.io") {
-                return Err(anyhow::anyhow!("`dragon update` is only supported for ACR images!"));
+    // the tags found are kept, whatever happened to the other WSLs
+    update_dockerwsl_file(dockerwsl_path, |current_dockerwsl_content| {
+        for wsl_conf in dockerwsl_content.wsls.iter().filter(|wsl_conf| updated_wsl_names.contains(&wsl_conf.name)) {
+            if let Some(current_wsl_conf) = current_dockerwsl_content.wsls.iter_mut().find(|current_wsl_conf| current_wsl_conf.name == wsl_conf.name) {
+                current_wsl_conf.latest = wsl_conf.latest.clone();
             }
+        }
+        Ok(())
+    }).with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+    
+    finish_batch("update", wsl_name.is_none(), fail_fast_error)
+}
+
+/// Ends a command going through several WSLs: prints the summary table if it went through all of them and fails if any WSL failed.
+fn finish_batch(command_name: &str, all_wsls: bool, fail_fast_error: Option<anyhow::Error>) -> Result<()> {
+    if all_wsls {
+        output::print_summary();
+    }
 
-            let registry_name_str = registry_name.as_str();
+    if let Some(error) = fail_fast_error {
+        return Err(error);
+    }
 
-            let private_registry_option = dockerwsl_content.private_registries.iter().find(|reg| reg.name.as_str() == registry_name_str);
+    let failed_wsls = output::failed_wsls();
+    if failed_wsls.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Could not {} {} WSL(s): `{}`!", command_name, failed_wsls.len(), failed_wsls.join("`, `")))
+    }
+}
 
-            if private_registry_option.is_some() {
-                let private_registry = private_registry_option.unwrap();
+fn update_wsl_conf(wsl_conf: &mut WSLConf, private_registries: &[Registry]) -> Result<WslResult> {
+    let image_url_str = wsl_conf.image.as_str();
 
-                if private_registry.tenant.is_none() {
-                    return Err(anyhow::anyhow!("Tenant is required in order to determine the most recent tag for the image `{}`!", &wsl_conf.name));
-                }
+    let image_reference = ImageReference::parse(image_url_str)
+        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url_str))?;
 
-                let username_str = private_registry.username.as_str();
-                let password_str = private_registry.password.as_str();
-                let tenant = private_registry.tenant.as_ref().unwrap();
-                let tenant_str = tenant.as_str();
-                let repository_name_str = repository_name.as_str();
-
-                let latest_tag = get_latest_tag(registry_name_str, repository_name_str, username_str, password_str, tenant_str, &update.az_cli)
-                    .with_context(|| format!("Could not get latest tag for repository {}.azurecr.io/{}", registry_name_str, repository_name_str))?;
-                
-                let latest_tag_str = latest_tag.as_str();
-                wsl_conf.latest = Some(latest_tag.clone());
-                println!("WSL `{}` latest property will be updated to `{}`!", &wsl_conf.name, latest_tag_str);
-            }
-        }
-    }
+    let registry_credentials = get_registry_credentials(image_reference.registry_name(), private_registries)?;
+    let mut registry_client = RegistryClient::new(image_reference.registry_name(), registry_credentials);
 
-    write_dockerwsl_file(dockerwsl_path, &dockerwsl_content)
-        .with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
-    
-    Ok(())
+    let tag_policy = wsl_conf.tag_policy.clone().unwrap_or_default();
+    let latest_tag = tag_policy.select_tag(&mut registry_client, image_reference.repository_path().as_str())
+        .with_context(|| format!("Could not get latest tag for image `{}`!", image_url_str))?;
+
+    message!("WSL `{}` latest property will be updated to `{}`!", &wsl_conf.name, &latest_tag);
+
+    let mut wsl_result = WslResult::new(&wsl_conf.name);
+    wsl_result.image = Some(wsl_conf.image.clone());
+    wsl_result.old_tag = wsl_conf.latest.clone();
+    wsl_result.new_tag = Some(latest_tag.clone());
+
+    wsl_conf.latest = Some(latest_tag);
+
+    Ok(wsl_result)
 }
 
 fn handle_run(run: Run) -> Result<()> {
@@ -283,15 +737,14 @@ fn handle_run(run: Run) -> Result<()> {
     let wsl_conf = wslconf_option.unwrap();
     let image_url_string = &wsl_conf.image;
 
-    let (_registry_name, _repository_name, tag) = extract_generic_image_details(image_url_string.as_str())
+    let image_reference = ImageReference::parse(image_url_string.as_str())
         .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url_string))?;
 
-    if tag.is_none() {
+    if image_reference.tag.is_none() && image_reference.digest.is_none() {
         return Err(anyhow::anyhow!("Could not find image tag in image URL `{}` in .dockerwsl file for WSL `{}`!", image_url_string, wsl_name));
     }
 
-    let tag_str = tag.unwrap();
-    let wsl_vm_name = get_wsl_wm_name(wsl_name.as_str(), tag_str.as_str())
+    let wsl_vm_name = get_wsl_wm_name(wsl_name.as_str(), image_reference.version_label().as_str())
         .with_context(|| format!("Could not compose WSL VM name from WSL name and tag!"))?;
 
     let mut wsl_run_command = Command::new(r#"wsl"#);
@@ -311,7 +764,7 @@ fn get_wsl_wm_name(wsl_name: &str, tag: &str) -> Result<String> {
     return Ok(format!("{}-{}", wsl_name, tag));
 }
 
-fn wsl_vm_exists(wsl_name: &str) -> Result<bool> {
+fn list_wsl_vms() -> Result<Vec<String>> {
     let mut wsl_list_command = Command::new(r#"wsl"#);
     wsl_list_command.args(&["-l", "-q"]);
 
@@ -321,352 +774,1681 @@ fn wsl_vm_exists(wsl_name: &str) -> Result<bool> {
     let stdout_string = String::from_utf8(wsl_list_command_output.stdout)
         .with_context(|| format!("Couldn't parse stdout!"))?;
 
-    let stdout_wsl_list: Vec<&str> = stdout_string.split(format!("\r{}\n{}", char::from(0), char::from(0)).as_str()).collect();
-
-    let existing_wsl = stdout_wsl_list.iter().find(|w| {
-        w.replace(char::from(0), "") == wsl_name
-    });
+    let stdout_wsl_list: Vec<String> = stdout_string.split(format!("\r{}\n{}", char::from(0), char::from(0)).as_str())
+        .map(|w| w.replace(char::from(0), ""))
+        .filter(|w| !w.trim().is_empty())
+        .collect();
 
-    if existing_wsl.is_some() { return Ok(true); }
-    else { return Ok(false); }
+    Ok(stdout_wsl_list)
 }
 
-fn handle_new(new: New) -> Result<()> {
-    let mut image_url = new.image;
-    let (registry_name_option, repository_name, tag_option) = extract_generic_image_details(image_url.as_str())
-        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url.as_str()))?;
+fn list_wsl_vms_verbose() -> Result<Vec<WSLVMStatus>> {
+    let mut wsl_list_command = Command::new(r#"wsl"#);
+    wsl_list_command.args(["-l", "-v"]);
 
-    if tag_option.is_none() {
-        image_url = format!("{}:latest", image_url);
-    }
+    let wsl_list_command_output = wsl_list_command.output()
+        .with_context(|| "`wsl -l -v` failed!")?;
 
-    let wsl_name = new.name.unwrap_or(repository_name.clone());
-    let wsl_name_str = wsl_name.as_str();
+    // `wsl` writes UTF-16, dropping the NUL bytes leaves the ASCII output
+    let stdout_string = String::from_utf8(wsl_list_command_output.stdout)
+        .with_context(|| "Couldn't parse stdout!")?
+        .replace(char::from(0), "");
+
+    let wsl_vms = stdout_string.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.trim_start_matches(|c: char| c == '*' || c.is_whitespace()).split_whitespace();
+            let name = columns.next()?;
+            let state = columns.next()?;
+            let version = columns.next()?;
+
+            Some(WSLVMStatus { name: name.to_string(), state: state.to_string(), version: version.to_string() })
+        })
+        .collect();
 
-    let wt_profile_id = uuid::Uuid::new_v4().to_hyphenated().to_string();
+    Ok(wsl_vms)
+}
 
-    let dockerwsl_path = &new.dockerwsl;
+fn wsl_vm_exists(wsl_name: &str) -> Result<bool> {
+    let wsl_vms = list_wsl_vms()
+        .with_context(|| "Could not list existing WSL VMs!")?;
 
-    if registry_name_option.is_some() && new.username.is_some() && new.password.is_some() {
-        let registry_name = registry_name_option.clone().unwrap();
-        let registry_name_str = registry_name.as_str();
-        let username = new.username.unwrap();
-        let username_str = username.as_str();
-        let password = new.password.unwrap();
-        let password_str = password.as_str();
+    Ok(wsl_vms.iter().any(|w| w == wsl_name))
+}
 
-        create_private_registry_record(registry_name_str, username_str, password_str, new.tenant, dockerwsl_path)
-            .with_context(|| format!("Could not create private registry record in .dockerwsl for `{}`!", registry_name_str))?;
-    }
+/// The WSL VMs among `wsl_vms` that dragon installed for `wsl_conf`. They are named `<name>-<tag>`, so `ubuntu-22.04`
+/// could just as well be a distro of the user's own: only the ones with a tag dragon installed (the one in use or one in
+/// the history) or with a folder among `install_folders`, the folders in the base install path of the WSL, are taken.
+/// `dev-tools-1` belongs to the configured WSL `dev-tools`, not to `dev`.
+fn get_wsl_vms_for_wsl(wsl_conf: &WSLConf, configured_wsl_names: &[&str], wsl_vms: &[String], install_folders: &[String]) -> Vec<String> {
+    let wsl_name = wsl_conf.name.as_str();
+    let wsl_vm_prefix = format!("{}-", wsl_name);
+    let installed_wsl_vm_names = get_installed_wsl_vm_names(wsl_conf);
+
+    wsl_vms.iter()
+        .filter(|vm| vm.starts_with(&wsl_vm_prefix))
+        .filter(|vm| {
+            !configured_wsl_names.iter().any(|other| {
+                other.len() > wsl_name.len() && vm.starts_with(&format!("{}-", other))
+            })
+        })
+        .filter(|vm| installed_wsl_vm_names.contains(vm) || install_folders.contains(vm))
+        .cloned()
+        .collect()
+}
 
-    handle_pull_for_image(registry_name_option, dockerwsl_path, image_url.as_str())
-        .with_context(|| format!("Could not handle pull for image `{}`!", image_url.as_str()))?;
+/// The names of the WSL VMs dragon installed for a WSL: the one of the image in use and the ones in its history.
+fn get_installed_wsl_vm_names(wsl_conf: &WSLConf) -> Vec<String> {
+    let mut tags: Vec<String> = wsl_conf.history.iter().map(|installed_version| installed_version.tag.clone()).collect();
+    if let Ok(image_reference) = ImageReference::parse(&wsl_conf.image) {
+        tags.push(image_reference.version_label());
+    }
 
-    let tag = tag_option.unwrap_or("latest".to_string());
-    let wsl_vm_name = get_wsl_wm_name(repository_name.as_str(), tag.as_str())
-        .with_context(|| format!("Could not compose WSL VM name from WSL name and tag!"))?;
-    let wsl_vm_name_str = wsl_vm_name.as_str();
+    tags.iter().filter_map(|tag| get_wsl_wm_name(&wsl_conf.name, tag).ok()).collect()
+}
 
-    let base_install_path = determine_base_install_path(&new.base_install_path, dockerwsl_path, wsl_name_str)
-        .with_context(|| format!("Could not determine base install path for WSL VM `{}`!", wsl_vm_name_str))?;
+/// The install folders of all WSL VMs in the places dragon installs them: the base install paths of the WSLs and the folders
+/// of the WSLs in `default_base_wsl_install_path`, including the ones of WSLs that are not configured anymore.
+fn list_dragon_install_folders(dockerwsl_content: &DockerWSLConf) -> Result<Vec<String>> {
+    let mut base_install_paths: Vec<PathBuf> = dockerwsl_content.wsls.iter().map(|wsl_conf| PathBuf::from(&wsl_conf.base_install_path)).collect();
+    if let Some(default_base_wsl_install_path) = &dockerwsl_content.default_base_wsl_install_path {
+        let default_base_wsl_install_path = Path::new(default_base_wsl_install_path);
+        for wsl_folder in list_install_folders(default_base_wsl_install_path)? {
+            base_install_paths.push(default_base_wsl_install_path.join(wsl_folder));
+        }
+    }
 
-    let temp_dir = Builder::new().prefix("dragon").tempdir()?;
-    let tar_path = export_docker_image_to_tar(image_url.as_str(), &temp_dir)
-        .with_context(|| format!("Could not export docker image `{}` to tar file!", image_url.as_str()))?;
+    let mut install_folders = vec![];
+    for base_install_path in base_install_paths.iter() {
+        install_folders.extend(list_install_folders(base_install_path)?);
+    }
 
-    create_wsl_vm_from_tar(wsl_vm_name_str, &tar_path, &base_install_path)
-        .with_context(|| format!("Could not create WSL VM with name `{}`", wsl_vm_name_str))?;
+    Ok(install_folders)
+}
 
-    create_dockerwsl_config_entry(dockerwsl_path, image_url.as_str(), wsl_name_str, wt_profile_id.as_str(), &base_install_path, tag.as_str())
-        .with_context(|| format!("Could not create the .dockerwsl config entry for the `{}` entry!", wsl_name_str))?;
+/// The names of the folders in a base install path, each the install folder of a WSL VM.
+fn list_install_folders(base_install_path: &Path) -> Result<Vec<String>> {
+    if !base_install_path.is_dir() {
+        return Ok(vec![]);
+    }
 
-    create_windows_terminal_profile(&new.wtconfig, wt_profile_id.as_str(), wsl_name_str)
-        .with_context(|| format!("Could not create Windows Terminal profile in settings.json for `{}`!", wsl_name_str))?;
+    let install_folders = std::fs::read_dir(base_install_path)
+        .with_context(|| format!("Could not read the base install path `{}`!", base_install_path.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
 
-    Ok(())
+    Ok(install_folders)
 }
 
-fn create_private_registry_record(registry_name_str: &str, username: &str, password: &str, tenant: Option<String>, dockerwsl_path: &PathBuf) -> Result<()> {
-    let mut dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
-        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
-
-    let private_registry_option = dockerwsl_content.private_registries.iter().find(|reg| reg.name.as_str() == registry_name_str);
-
-    if private_registry_option.is_some() {
-        println!("There are already credentials for private registry `{}! Will keep the existing ones.", registry_name_str);
-    } else {
-        let private_registry = Registry {
-            name: registry_name_str.to_string(),
-            username: username.to_string(),
-            password: password.to_string(),
-            tenant: tenant
-        };
+fn handle_remove(remove: Remove) -> Result<()> {
+    let dockerwsl_path = &remove.dockerwsl;
+    let wsl_name = remove.wsl.as_str();
 
-        dockerwsl_content.private_registries.insert(0, private_registry);
-    }
+    let dockerwsl_content = parse_dockerwslconf_file(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
 
-    write_dockerwsl_file(dockerwsl_path, &dockerwsl_content)
-        .with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+    let wsl_conf = dockerwsl_content.wsls.iter().find(|wsl| wsl.name == wsl_name)
+        .with_context(|| format!("Could not find .dockerwsl config with name `{}`!", wsl_name))?;
 
-    println!("Credentials for private registry `{}` have been added to the .dockerwsl file!", registry_name_str);
+    let configured_wsl_names: Vec<&str> = dockerwsl_content.wsls.iter().map(|wsl| wsl.name.as_str()).collect();
+    let wsl_vms = list_wsl_vms()
+        .with_context(|| "Could not list existing WSL VMs!")?;
 
-    Ok(())
-}
+    let base_install_path = PathBuf::from(&wsl_conf.base_install_path);
+    let install_folders = list_install_folders(&base_install_path)?;
 
-fn determine_login(registry_name_option: Option<String>, dockerwsl_path: &PathBuf) -> Result<()> {
-    if registry_name_option.is_some() {
-        let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
-            .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
-        
-        let registry_name_string = registry_name_option.unwrap();
-        let registry_name_str = registry_name_string.as_str();
+    let mut wsl_result = WslResult::new(wsl_name);
+    wsl_result.image = Some(wsl_conf.image.clone());
+    wsl_result.profile_guid = Some(wsl_conf.windows_terminal_profile_id.clone());
 
-        let private_registry_option = dockerwsl_content.private_registries.iter().find(|reg| reg.name.as_str() == registry_name_str);
+    for wsl_vm_name in get_wsl_vms_for_wsl(wsl_conf, &configured_wsl_names, &wsl_vms, &install_folders) {
+        delete_wsl_vm(wsl_vm_name.as_str())
+            .with_context(|| format!("Could not delete WSL VM `{}`!", &wsl_vm_name))?;
 
-        if private_registry_option.is_some() {
-            let private_registry = private_registry_option.unwrap();
+        if !dry_run::is_enabled() {
+            message!("WSL VM `{}` has been unregistered!", &wsl_vm_name);
+        }
+        wsl_result.removed_vms.push(wsl_vm_name);
+    }
 
-            let username_str = private_registry.username.as_str();
-            let password_str = private_registry.password.as_str();
+    delete_wsl_install_folders(wsl_conf, &configured_wsl_names, &base_install_path, &install_folders, &wsl_result.removed_vms)
+        .with_context(|| format!("Could not delete the install folders of WSL `{}`!", wsl_name))?;
 
-            docker_login(registry_name_str, username_str, password_str)
-                .with_context(|| format!("Could not `docker login` for registry `{}`", registry_name_str))?;
+    // profiles written to a settings.json file before are removed, whatever the backend is now
+    let profile_backend = dockerwsl_content.windows_terminal_profile_backend.unwrap_or_default();
+    let mut wt_settings_paths: Vec<PathBuf> = wsl_conf.windows_terminal_profiles.iter()
+        .map(|wt_profile_state| PathBuf::from(&wt_profile_state.settings_path))
+        .collect();
+    if profile_backend == ProfileBackend::Settings {
+        match get_windows_terminal_settings_paths(&remove.wtconfig, &dockerwsl_content.windows_terminal_installs) {
+            Ok(discovered_settings_paths) => {
+                for (_install, settings_path) in discovered_settings_paths {
+                    if !wt_settings_paths.contains(&settings_path) {
+                        wt_settings_paths.push(settings_path);
+                    }
+                }
+            },
+            Err(error) => debug!("No other Windows Terminal settings files to remove the profile from: {:#}", error)
         }
     }
 
-    Ok(())
-}
-
-fn determine_base_install_path(new_install_location: &Option<PathBuf>, dockerwsl_path: &PathBuf, wsl_name_str: &str) -> Result<PathBuf> {
-    if new_install_location.is_none() {
-        let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
-            .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
-        if dockerwsl_content.default_base_wsl_install_path.is_none() {
-            return Err(anyhow::anyhow!("No install location was passed and no `default_wsl_install_location` value is defined in .dockerwsl file!"));
-        } else {
-            let parent_folder_wsl_path = PathBuf::from(dockerwsl_content.default_base_wsl_install_path.unwrap());
-            let wsl_path = parent_folder_wsl_path.join(wsl_name_str);
-            return Ok(wsl_path);
+    for settings_path in wt_settings_paths.iter() {
+        if !settings_path.exists() {
+            message!("Windows Terminal settings file `{}` doesn't exist anymore, skipping it.", settings_path.display());
+            continue;
         }
-    } else {
-        return Ok(new_install_location.clone().unwrap());
+
+        remove_windows_terminal_profile(settings_path, wsl_conf.windows_terminal_profile_id.as_str(), wsl_name)
+            .with_context(|| format!("Could not remove Windows Terminal profile from `{}` for `{}`!", settings_path.display(), wsl_name))?;
     }
-}
 
-fn generate_rand_filename() -> Result<String> {
-    let rand_string: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(30)
-        .collect();
+    remove_dockerwsl_config_entry(dockerwsl_path, wsl_name)
+        .with_context(|| format!("Could not remove the .dockerwsl config entry for the `{}` entry!", wsl_name))?;
 
-    return Ok(rand_string);
-}
+    lockfile::remove_from_lock_file(&lockfile::path(dockerwsl_path), wsl_name)
+        .with_context(|| format!("Could not remove `{}` from the lock file!", wsl_name))?;
 
-fn export_docker_image_to_tar(image_url_str: &str, temp_dir: &TempDir) -> Result<PathBuf> {
-    let docker_container_id = docker_create(image_url_str)
-        .with_context(|| format!("Could not `docker create {}`!", image_url_str))?;
-    let random_filename = generate_rand_filename()
-        .with_context(|| format!("Could not generate a random filename!"))?;
-    let tar_file_path = temp_dir.path().join(random_filename);
+    if profile_backend == ProfileBackend::Fragment {
+        update_windows_terminal_fragment(dockerwsl_path)
+            .with_context(|| format!("Could not remove Windows Terminal profile from the fragment for `{}`!", wsl_name))?;
+    }
 
-    docker_export(&docker_container_id, &tar_file_path)
-        .with_context(|| format!("Could not export docker container with id `{}` to tar file `{:#?}`!", &docker_container_id, &tar_file_path))?;
+    output::record(wsl_result);
 
-    Ok(tar_file_path)
+    Ok(())
 }
 
-fn docker_create(image_url_str: &str) -> Result<String> {
-    let mut docker_create_command = Command::new(r#"docker"#);
-    docker_create_command.args(&["create", image_url_str]);
+fn handle_status(status: Status) -> Result<()> {
+    let dockerwsl_path = &status.dockerwsl;
+    let wsl_name = &status.wsl;
 
-    let docker_create_command_output = docker_create_command.output()
-        .with_context(|| format!("`docker create {}` failed!", image_url_str))?;
+    let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    let wsl_vms = list_wsl_vms_verbose()
+        .with_context(|| "Could not list existing WSL VMs!")?;
+    let wsl_vm_names: Vec<String> = wsl_vms.iter().map(|vm| vm.name.clone()).collect();
+
+    // the profiles of every place they are written to, named for the output
+    let mut wt_profile_sources: Vec<(String, Vec<serde_json::Value>)> = vec![];
+    match dockerwsl_content.windows_terminal_profile_backend.unwrap_or_default() {
+        ProfileBackend::Fragment => {
+            let fragment_profiles = list_windows_terminal_fragment_profiles()
+                .with_context(|| "Could not read the profiles from the Windows Terminal fragment!")?;
+            wt_profile_sources.push(("fragment".to_string(), fragment_profiles));
+        },
+        ProfileBackend::Settings => {
+            let wt_settings_paths = get_windows_terminal_settings_paths(&status.wtconfig, &dockerwsl_content.windows_terminal_installs)
+                .unwrap_or_default();
+
+            for (install, settings_path) in wt_settings_paths.iter() {
+                let wt_profiles = list_windows_terminal_profiles(settings_path)
+                    .with_context(|| format!("Could not read the profiles from Windows Terminal settings file `{:#?}`!", settings_path))?;
+                wt_profile_sources.push((install.name().to_string(), wt_profiles));
+            }
+        },
+        ProfileBackend::Disabled => {}
+    }
 
-    let stdout_string = String::from_utf8(docker_create_command_output.stdout)
-        .with_context(|| format!("Couldn't parse stdout!"))?;
+    let configured_wsl_names: Vec<&str> = dockerwsl_content.wsls.iter().map(|wsl| wsl.name.as_str()).collect();
+    let mut configured_wsl_vm_names = vec![];
+    let mut drift_count = 0;
 
-    Ok(stdout_string.trim().replace(char::from(0), ""))
-}
+    for wsl_conf in dockerwsl_content.wsls.iter() {
+        if let Some(name) = wsl_name {
+            if name.ne(&wsl_conf.name) {
+                continue;
+            }
+        }
+
+        let image_reference = ImageReference::parse(&wsl_conf.image)
+            .with_context(|| format!("Could not extract Docker image details from URL `{}`!", &wsl_conf.image))?;
+        let current_wsl_vm_name = Some(get_wsl_wm_name(&wsl_conf.name, &image_reference.version_label())?);
+        let latest_wsl_vm_name = match &wsl_conf.latest {
+            Some(latest) => Some(get_wsl_wm_name(&wsl_conf.name, latest)?),
+            None => None
+        };
+
+        message!("WSL `{}`", &wsl_conf.name);
+        message!("    image:   {}", &wsl_conf.image);
+        message!("    latest:  {}", wsl_conf.latest.as_deref().unwrap_or("-"));
+
+        let mut wsl_result = WslResult::new(&wsl_conf.name);
+        wsl_result.image = Some(wsl_conf.image.clone());
+        wsl_result.latest_tag = wsl_conf.latest.clone();
+        wsl_result.vm_name = current_wsl_vm_name.clone();
+        wsl_result.profile_guid = Some(wsl_conf.windows_terminal_profile_id.clone());
+
+        let wt_profile_guid_with_braces = format!("{{{}}}", &wsl_conf.windows_terminal_profile_id);
+        let mut missing_profile_sources = vec![];
+        if wt_profile_sources.is_empty() {
+            message!("    profile: {} (not checked)", &wt_profile_guid_with_braces);
+        }
+        for (source, profiles) in wt_profile_sources.iter() {
+            let profile_exists = profiles.iter().any(|profile| get_windows_terminal_profile_guid(profile) == Some(wt_profile_guid_with_braces.as_str()));
+            if profile_exists {
+                message!("    profile: {} in {} (present)", &wt_profile_guid_with_braces, source);
+            } else {
+                message!("    profile: {} in {} (MISSING)", &wt_profile_guid_with_braces, source);
+                missing_profile_sources.push(source.as_str());
+            }
+        }
+
+        let install_folders = list_install_folders(Path::new(&wsl_conf.base_install_path))?;
+        let wsl_conf_vm_names = get_wsl_vms_for_wsl(wsl_conf, &configured_wsl_names, &wsl_vm_names, &install_folders);
+        configured_wsl_vm_names.extend(wsl_conf_vm_names.iter().cloned());
+        message!("    WSL VMs:");
+        if wsl_conf_vm_names.is_empty() {
+            message!("        none installed");
+        }
+        for wsl_vm in wsl_vms.iter().filter(|vm| wsl_conf_vm_names.contains(&vm.name)) {
+            let vm_status = VmStatus {
+                name: wsl_vm.name.clone(),
+                state: wsl_vm.state.clone(),
+                version: wsl_vm.version.clone(),
+                in_use: Some(&wsl_vm.name) == current_wsl_vm_name.as_ref(),
+                latest: Some(&wsl_vm.name) == latest_wsl_vm_name.as_ref()
+            };
+
+            let mut markers = vec![];
+            if vm_status.in_use { markers.push("in use"); }
+            if vm_status.latest { markers.push("latest"); }
+
+            message!("        {} {} (WSL {}) {}", &wsl_vm.name, &wsl_vm.state, &wsl_vm.version, markers.join(", "));
+            wsl_result.vms.push(vm_status);
+        }
+
+        let mut drifts = vec![];
+        if let Some(current) = &current_wsl_vm_name {
+            if !wsl_conf_vm_names.contains(current) {
+                drifts.push(format!("WSL VM `{}` for the image in use is not installed!", current));
+            }
+        }
+        if let Some(latest) = &latest_wsl_vm_name {
+            if Some(latest) != current_wsl_vm_name.as_ref() {
+                drifts.push(format!("latest tag `{}` is not in use yet, run `dragon upgrade`.", wsl_conf.latest.as_deref().unwrap_or_default()));
+            }
+        }
+        for source in missing_profile_sources.iter() {
+            drifts.push(format!("Windows Terminal profile `{}` is missing in {}!", &wt_profile_guid_with_braces, source));
+        }
+
+        for drift in drifts.iter() {
+            message!("    DRIFT: {}", drift);
+        }
+        drift_count += drifts.len();
+
+        wsl_result.drifts = drifts;
+        output::record(wsl_result);
+    }
+
+    if wsl_name.is_none() {
+        // only distros installed where dragon installs them, the others (Ubuntu, docker-desktop, ...) are none of dragon's business
+        let dragon_install_folders = list_dragon_install_folders(&dockerwsl_content)?;
+        let orphaned_wsl_vms: Vec<&WSLVMStatus> = wsl_vms.iter()
+            .filter(|vm| !configured_wsl_vm_names.contains(&vm.name))
+            .filter(|vm| dragon_install_folders.contains(&vm.name))
+            .collect();
+
+        if !orphaned_wsl_vms.is_empty() {
+            message!("WSL VMs that don't belong to any .dockerwsl entry:");
+            for wsl_vm in orphaned_wsl_vms.iter() {
+                message!("    DRIFT: {} {} (WSL {})", &wsl_vm.name, &wsl_vm.state, &wsl_vm.version);
+                output::record_drift(format!("WSL VM `{}` doesn't belong to any .dockerwsl entry!", &wsl_vm.name));
+            }
+            drift_count += orphaned_wsl_vms.len();
+        }
+
+        for (source, profiles) in wt_profile_sources.iter() {
+            let orphaned_profiles: Vec<&serde_json::Value> = profiles.iter()
+                .filter(|profile| is_dragon_windows_terminal_profile(profile))
+                .filter(|profile| !dockerwsl_content.wsls.iter().any(|wsl| {
+                    get_windows_terminal_profile_guid(profile) == Some(format!("{{{}}}", &wsl.windows_terminal_profile_id).as_str())
+                }))
+                .collect();
+
+            if !orphaned_profiles.is_empty() {
+                message!("Windows Terminal profiles in {} running `dragon` that don't belong to any .dockerwsl entry:", source);
+                for profile in orphaned_profiles.iter() {
+                    let wt_profile_guid = get_windows_terminal_profile_guid(profile).unwrap_or("-");
+                    message!("    DRIFT: {} {}", wt_profile_guid, profile.get("name").and_then(|name| name.as_str()).unwrap_or("-"));
+                    output::record_drift(format!("Windows Terminal profile `{}` in {} doesn't belong to any .dockerwsl entry!", wt_profile_guid, source));
+                }
+                drift_count += orphaned_profiles.len();
+            }
+        }
+    }
+
+    if drift_count == 0 {
+        message!("No drift found between .dockerwsl and the machine.");
+    } else {
+        message!("Found {} drift(s) between .dockerwsl and the machine!", drift_count);
+    }
+
+    Ok(())
+}
+
+fn handle_cleanup(cleanup: Cleanup) -> Result<()> {
+    let dockerwsl_path = &cleanup.dockerwsl;
+    let wsl_name = &cleanup.wsl;
+
+    let dockerwsl_content = parse_dockerwslconf_file(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    let configured_wsl_names: Vec<&str> = dockerwsl_content.wsls.iter().map(|wsl| wsl.name.as_str()).collect();
+    let wsl_vms = list_wsl_vms()
+        .with_context(|| "Could not list existing WSL VMs!")?;
+
+    for wsl_conf in dockerwsl_content.wsls.iter() {
+        match wsl_name {
+            Some(name) => {
+                if name.ne(&wsl_conf.name) {
+                    debug!("Passed wsl name (`{}`) doesn't match current config entry name, will SKIP it!", &name);
+                    continue;
+                } else {
+                    debug!("Passed wsl name (`{}`) matches current config entry name, will process it!", &name);
+                }
+            },
+            None => { debug!("No wsl name passed to `cleanup`, will clean up all wsls in the config file!"); }
+        }
+
+        let keep_versions = cleanup.keep
+            .or(wsl_conf.keep_versions)
+            .or(dockerwsl_content.default_keep_versions)
+            .unwrap_or(1);
+
+        match cleanup_wsl_conf(wsl_conf, keep_versions, &configured_wsl_names, &wsl_vms) {
+            Ok(wsl_result) => output::record(wsl_result),
+            Err(error) => {
+                output::record(WslResult::failed(&wsl_conf.name, &error));
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn cleanup_wsl_conf(wsl_conf: &WSLConf, keep_versions: usize, configured_wsl_names: &[&str], wsl_vms: &[String]) -> Result<WslResult> {
+    if keep_versions == 0 {
+        return Err(anyhow::anyhow!("The number of WSL VMs to keep for WSL `{}` must be at least 1!", &wsl_conf.name));
+    }
+
+    let image_reference = ImageReference::parse(&wsl_conf.image)
+        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", &wsl_conf.image))?;
+    let current_wsl_vm_name = Some(get_wsl_wm_name(&wsl_conf.name, &image_reference.version_label())?);
+
+    let base_install_path = PathBuf::from(&wsl_conf.base_install_path);
+    let install_folders = list_install_folders(&base_install_path)?;
+    let mut wsl_conf_vms = get_wsl_vms_for_wsl(wsl_conf, configured_wsl_names, wsl_vms, &install_folders);
+    let mut wsl_result = WslResult::new(&wsl_conf.name);
+
+    // newest first, the WSL VM currently in use always comes first so it is never removed
+    wsl_conf_vms.sort_by_key(|vm| std::cmp::Reverse(get_wsl_vm_install_time(&base_install_path.join(vm))));
+    wsl_conf_vms.sort_by_key(|vm| Some(vm) != current_wsl_vm_name.as_ref());
+
+    if wsl_conf_vms.len() <= keep_versions {
+        message!("WSL `{}` has {} WSL VM(s), nothing to clean up.", &wsl_conf.name, wsl_conf_vms.len());
+        return Ok(wsl_result);
+    }
+
+    for wsl_vm_name in wsl_conf_vms.iter().skip(keep_versions) {
+        delete_wsl_vm(wsl_vm_name)
+            .with_context(|| format!("Could not delete WSL VM `{}`!", wsl_vm_name))?;
+
+        if !dry_run::is_enabled() {
+            message!("WSL VM `{}` has been unregistered!", wsl_vm_name);
+        }
+
+        delete_wsl_install_folder(&base_install_path.join(wsl_vm_name))
+            .with_context(|| format!("Could not delete the install folder of WSL VM `{}`!", wsl_vm_name))?;
+        wsl_result.removed_vms.push(wsl_vm_name.clone());
+    }
+
+    if !dry_run::is_enabled() {
+        message!("WSL `{}` has been cleaned up, kept {} WSL VM(s)!", &wsl_conf.name, keep_versions);
+    }
+
+    Ok(wsl_result)
+}
+
+/// When the WSL VM was installed, based on its install folder. WSL VMs without a folder are considered the oldest.
+fn get_wsl_vm_install_time(install_path: &Path) -> Option<std::time::SystemTime> {
+    let metadata = std::fs::metadata(install_path).ok()?;
+    metadata.created().or_else(|_| metadata.modified()).ok()
+}
+
+/// Deletes the install folders of the WSL VMs dragon installed for `wsl_conf` and of the `removed_vms` just unregistered.
+/// Other folders in the base install path are left alone.
+fn delete_wsl_install_folders(wsl_conf: &WSLConf, configured_wsl_names: &[&str], base_install_path: &Path, install_folders: &[String], removed_vms: &[String]) -> Result<()> {
+    if !base_install_path.is_dir() {
+        debug!("Base install path `{}` doesn't exist, nothing to delete.", base_install_path.display());
+        return Ok(());
+    }
+
+    for install_folder in get_wsl_vms_for_wsl(wsl_conf, configured_wsl_names, install_folders, removed_vms) {
+        delete_wsl_install_folder(&base_install_path.join(&install_folder))?;
+    }
+
+    // only removes the base install path if nothing else is left in it
+    if !dry_run::is_enabled() && std::fs::remove_dir(base_install_path).is_ok() {
+        debug!("Removed empty base install path `{}`.", base_install_path.display());
+    }
+
+    Ok(())
+}
+
+fn delete_wsl_install_folder(install_path: &Path) -> Result<()> {
+    if !install_path.exists() {
+        debug!("Install folder `{}` doesn't exist, nothing to delete.", install_path.display());
+        return Ok(());
+    }
+
+    if dry_run::is_enabled() {
+        message!("Would delete install folder `{}`.", install_path.display());
+        return Ok(());
+    }
+
+    std::fs::remove_dir_all(install_path)
+        .with_context(|| format!("Could not delete install folder `{}`!", install_path.display()))?;
+
+    message!("Install folder `{}` has been deleted!", install_path.display());
+
+    Ok(())
+}
+
+fn remove_dockerwsl_config_entry(dockerwsl_path: &PathBuf, wsl_name: &str) -> Result<()> {
+    update_dockerwsl_file(dockerwsl_path, |dockerwsl_content| {
+        dockerwsl_content.wsls.retain(|wsl| wsl.name != wsl_name);
+        Ok(())
+    }).with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+
+    if !dry_run::is_enabled() {
+        message!("WSL config for `{}` has been removed from the .dockerwsl file!", wsl_name);
+    }
+
+    Ok(())
+}
+
+fn handle_new(new: New) -> Result<()> {
+    let temp_dir = Builder::new().prefix("dragon").tempdir()?;
+    let image_archive = match &new.from_archive {
+        Some(archive_path) => Some(ImageArchive::open(archive_path, temp_dir.path())
+            .with_context(|| format!("Could not read image archive `{}`!", archive_path.display()))?),
+        None => None
+    };
+
+    let image_url_option = match new.image {
+        Some(image) => image,
+        None => image_archive.as_ref().and_then(|archive| archive.image_name.clone())
+            .with_context(|| "The image archive doesn't contain an image name, pass it with `--image`!")?
+    };
+    let mut image_reference = ImageReference::parse(image_url_option.as_str())
+        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url_option.as_str()))?;
+
+    if image_reference.tag.is_none() && image_reference.digest.is_none() {
+        image_reference.tag = Some("latest".to_string());
+    }
+    let image_url = image_reference.to_string();
+    let registry_name_option = image_reference.registry_name().map(|registry_name| registry_name.to_string());
+
+    let wsl_name = new.name.unwrap_or(image_reference.short_name().to_string());
+    let wsl_name_str = wsl_name.as_str();
+
+    let wt_profile_id = uuid::Uuid::new_v4().to_hyphenated().to_string();
+
+    let dockerwsl_path = &new.dockerwsl;
+
+    let mut dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+    let profile_backend = get_profile_backend(new.no_terminal_profile, &dockerwsl_content);
+    let wt_settings_paths = match profile_backend {
+        ProfileBackend::Settings => get_windows_terminal_settings_paths(&new.wtconfig, &dockerwsl_content.windows_terminal_installs)?,
+        ProfileBackend::Fragment | ProfileBackend::Disabled => vec![]
+    };
+
+    // before anything is pulled, imported or pinned for the new WSL
+    if dockerwsl_content.wsls.iter().any(|wsl| wsl.name == wsl_name) {
+        return Err(anyhow::anyhow!("There is already a dockerwsl config with the name `{}`!", wsl_name));
+    }
+
+    let password_ref = match (new.password, new.password_env, new.password_file) {
+        (Some(password), _, _) => Some(SecretRef::Plain(password)),
+        (_, Some(env), _) => Some(SecretRef::Env { env }),
+        (_, _, Some(file)) => Some(SecretRef::File { file }),
+        (None, None, None) => None
+    };
+    let has_registry_credentials = new.credential_helper.is_some() || (new.username.is_some() && password_ref.is_some());
+
+    if image_archive.is_none() && registry_name_option.is_some() && has_registry_credentials {
+        let registry_name = registry_name_option.clone().unwrap();
+        let registry_name_str = registry_name.as_str();
+
+        let private_registry = Registry {
+            name: registry_name.clone(),
+            username: new.username,
+            password: password_ref,
+            credential_helper: new.credential_helper,
+            tenant: new.tenant
+        };
+
+        create_private_registry_record(private_registry, dockerwsl_path)
+            .with_context(|| format!("Could not create private registry record in .dockerwsl for `{}`!", registry_name_str))?;
+    }
+
+    let runtime = get_runtime(new.runtime, dockerwsl_path)?;
+    if image_archive.is_none() {
+        handle_pull_for_image(runtime, dockerwsl_path, &image_reference)
+            .with_context(|| format!("Could not handle pull for image `{}`!", image_url.as_str()))?;
+    }
+
+    let tag = image_reference.version_label();
+    let wsl_vm_name = get_wsl_wm_name(wsl_name_str, tag.as_str())
+        .with_context(|| format!("Could not compose WSL VM name from WSL name and tag!"))?;
+    let wsl_vm_name_str = wsl_vm_name.as_str();
+
+    let base_install_path = determine_base_install_path(&new.base_install_path, dockerwsl_path, wsl_name_str)
+        .with_context(|| format!("Could not determine base install path for WSL VM `{}`!", wsl_vm_name_str))?;
+
+    let tar_path = export_image_to_tar(runtime, dockerwsl_path, &image_reference, image_archive.as_ref(), &temp_dir)
+        .with_context(|| format!("Could not export docker image `{}` to tar file!", image_url.as_str()))?;
+
+    create_wsl_vm_from_tar(wsl_vm_name_str, &tar_path, &base_install_path)
+        .with_context(|| format!("Could not create WSL VM with name `{}`", wsl_vm_name_str))?;
+
+    let digest = resolve_image_digest(runtime, dockerwsl_path, &image_reference, image_archive.as_ref());
+    lockfile::update_lock_file(&lockfile::path(dockerwsl_path), wsl_name_str, image_url.as_str(), digest.as_deref())
+        .with_context(|| format!("Could not pin the image of `{}` in the lock file!", wsl_name_str))?;
+    let installed_version = InstalledVersion::new(image_url.as_str(), tag.as_str(), digest, &base_install_path.join(wsl_vm_name_str));
+
+    let base_install_path_str = base_install_path.to_str().with_context(|| "Could not convert install path to &str!")?;
+    let wt_profile_states = wt_settings_paths.iter()
+        .map(|(install, settings_path)| WindowsTerminalProfileState { install: *install, settings_path: settings_path.to_string_lossy().into_owned() })
+        .collect();
+
+    let wslconf = WSLConf {
+        name: wsl_name.clone(),
+        image: image_url.clone(),
+        latest: Some(installed_version.tag.clone()),
+        base_install_path: base_install_path_str.to_string(),
+        windows_terminal_profile_id: wt_profile_id.clone(),
+        archive: new.from_archive.as_ref().map(|p| p.to_string_lossy().into_owned()),
+        tag_policy: None,
+        keep_versions: None,
+        windows_terminal_profile: None,
+        windows_terminal_profiles: wt_profile_states,
+        history: vec![installed_version]
+    };
+
+    let mut wsl_result = WslResult::new(wsl_name_str);
+    wsl_result.image = Some(image_url.clone());
+    wsl_result.new_tag = Some(tag.clone());
+    wsl_result.vm_name = Some(wsl_vm_name.clone());
+    wsl_result.install_path = Some(base_install_path.join(wsl_vm_name_str).to_string_lossy().into_owned());
+    wsl_result.profile_guid = Some(wt_profile_id.clone());
+    wsl_result.digest = wslconf.history[0].digest.clone();
+
+    // the fragment is generated from the planned WSLs, with `--dry-run` there is no written .dockerwsl to read them from
+    dockerwsl_content.wsls.insert(0, wslconf.clone());
+    create_dockerwsl_config_entry(dockerwsl_path, wslconf)
+        .with_context(|| format!("Could not create the .dockerwsl config entry for the `{}` entry!", wsl_name_str))?;
+
+    match profile_backend {
+        ProfileBackend::Settings => {
+            for (_install, settings_path) in wt_settings_paths.iter() {
+                create_windows_terminal_profile(settings_path, wt_profile_id.as_str(), wsl_name_str, &get_profile_templates(&dockerwsl_content.default_windows_terminal_profile, &None))
+                    .with_context(|| format!("Could not create Windows Terminal profile in `{}` for `{}`!", settings_path.display(), wsl_name_str))?;
+            }
+        },
+        ProfileBackend::Fragment => write_windows_terminal_fragment(&dockerwsl_content)
+            .with_context(|| format!("Could not create Windows Terminal profile in the fragment for `{}`!", wsl_name_str))?,
+        ProfileBackend::Disabled => message!("No Windows Terminal profile has been created for `{}`, connect to it with `dragon run -w {}`.", wsl_name_str, wsl_name_str)
+    }
+
+    output::record(wsl_result);
+
+    Ok(())
+}
+
+fn create_private_registry_record(private_registry: Registry, dockerwsl_path: &PathBuf) -> Result<()> {
+    let registry_name = private_registry.name.clone();
+    let registry_name_str = registry_name.as_str();
+
+    update_dockerwsl_file(dockerwsl_path, |dockerwsl_content| {
+        let private_registry_option = dockerwsl_content.private_registries.iter().find(|reg| reg.name.as_str() == registry_name_str);
+
+        if private_registry_option.is_some() {
+            message!("There are already credentials for private registry `{}! Will keep the existing ones.", registry_name_str);
+        } else {
+            if let Some(SecretRef::Plain(_)) = &private_registry.password {
+                message!("The password for private registry `{}` is stored as is in .dockerwsl, consider `--password-env`, `--password-file` or `--credential-helper` instead.", registry_name_str);
+            }
+
+            dockerwsl_content.private_registries.insert(0, private_registry);
+        }
+        Ok(())
+    }).with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+
+    message!("Credentials for private registry `{}` have been added to the .dockerwsl file!", registry_name_str);
+
+    Ok(())
+}
+
+fn get_registry_credentials(registry_name_option: Option<&str>, private_registries: &[Registry]) -> Result<Option<RegistryCredentials>> {
+    let registry_name = match registry_name_option {
+        Some(registry_name) => registry_name,
+        None => return Ok(None)
+    };
+
+    private_registries.iter()
+        .find(|reg| reg.name == registry_name)
+        .map(|reg| reg.credentials())
+        .transpose()
+}
+
+fn determine_login(container_runtime: &dyn ContainerRuntime, registry_name_option: Option<&str>, dockerwsl_path: &PathBuf) -> Result<()> {
+    if let Some(registry_name_str) = registry_name_option {
+        let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+            .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
+
+        if let Some(private_registry) = dockerwsl_content.private_registries.iter().find(|reg| reg.name.as_str() == registry_name_str) {
+            let registry_credentials = private_registry.credentials()?;
+
+            container_runtime.login(registry_name_str, registry_credentials.username.as_str(), registry_credentials.password.as_str())
+                .with_context(|| format!("Could not `{} login` for registry `{}`", container_runtime.binary(), registry_name_str))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn determine_base_install_path(new_install_location: &Option<PathBuf>, dockerwsl_path: &PathBuf, wsl_name_str: &str) -> Result<PathBuf> {
+    if new_install_location.is_none() {
+        let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+            .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+        if dockerwsl_content.default_base_wsl_install_path.is_none() {
+            return Err(anyhow::anyhow!("No install location was passed and no `default_wsl_install_location` value is defined in .dockerwsl file!"));
+        } else {
+            let parent_folder_wsl_path = PathBuf::from(dockerwsl_content.default_base_wsl_install_path.unwrap());
+            let wsl_path = parent_folder_wsl_path.join(wsl_name_str);
+            return Ok(wsl_path);
+        }
+    } else {
+        return Ok(new_install_location.clone().unwrap());
+    }
+}
+
+fn generate_rand_filename() -> Result<String> {
+    let rand_string: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(30)
+        .collect();
+
+    return Ok(rand_string);
+}
+
+fn export_container_image_to_tar(container_runtime: &dyn ContainerRuntime, image_url_str: &str, temp_dir: &TempDir) -> Result<PathBuf> {
+    let container_id = container_runtime.create(image_url_str)
+        .with_context(|| format!("Could not `{} create {}`!", container_runtime.binary(), image_url_str))?;
+    let random_filename = generate_rand_filename()
+        .with_context(|| "Could not generate a random filename!")?;
+    let tar_file_path = temp_dir.path().join(random_filename);
+
+    let export_result = container_runtime.export(&container_id, &tar_file_path)
+        .with_context(|| format!("Could not export container with id `{}` to tar file `{:#?}`!", &container_id, &tar_file_path));
+
+    // the container was only needed for the export
+    if let Err(error) = container_runtime.remove(&container_id) {
+        debug!("Could not remove container `{}`: {:#}", &container_id, error);
+    }
+
+    export_result?;
+    Ok(tar_file_path)
+}
+
+fn create_dockerwsl_config_entry(dockerwsl_path: &PathBuf, wslconf: WSLConf) -> Result<()> {
+    let wsl_name = wslconf.name.clone();
+
+    update_dockerwsl_file(dockerwsl_path, |dockerwsl_content| {
+        let existing_dockerwsl = dockerwsl_content.wsls.iter().find(|wsl| wsl.name == wsl_name);
+
+        if existing_dockerwsl.is_some() {
+            return Err(anyhow::anyhow!("There is already a dockerwsl config with the name `{}`!", wsl_name));
+        }
+
+        dockerwsl_content.wsls.insert(0, wslconf);
+        Ok(())
+    }).with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+
+    if !dry_run::is_enabled() {
+        message!("WSL config for `{}` has been added to the .dockerwsl file!", wsl_name);
+    }
+
+    Ok(())
+}
+
+fn handle_pull(pull: Pull) -> Result<()> {
+    let dockerwsl_path = &pull.dockerwsl;
+    let wsl_name = &pull.wsl;
+    
+    let dockerwsl_content = parse_dockerwslconf_file(&dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
+    let runtime = pull.runtime.or(dockerwsl_content.runtime).unwrap_or_default();
+    let fail_fast = pull.fail_fast || wsl_name.is_some();
+    let mut selected_wsl_confs = vec![];
+
+    for wsl_conf in dockerwsl_content.wsls.iter() {
+        match wsl_name {
+            Some(name) => {
+                if name.ne(&wsl_conf.name) {
+                    debug!("Passed wsl name (`{}`) doesn't match current config entry name, will SKIP it!", &name);
+                    continue;
+                } else {
+                    debug!("Passed wsl name (`{}`) matches current config entry name, will process it!", &name);
+                }
+            },
+            None => { debug!("No wsl name passed to `upgrade`, will upgrade all wsls in the config file!"); }
+        }
+
+        if wsl_conf.archive.is_some() {
+            message!("WSL `{}` is created from an image archive, nothing to pull!", &wsl_conf.name);
+            continue;
+        }
+
+        selected_wsl_confs.push(wsl_conf);
+    }
+
+    let parallel = pull.jobs > 1;
+    let outcomes = jobs::run(pull.jobs, selected_wsl_confs, |wsl_conf| {
+        output::set_message_prefix(if parallel { Some(wsl_conf.name.as_str()) } else { None });
+
+        match pull_wsl_conf(wsl_conf, runtime, dockerwsl_path) {
+            Ok(wsl_result) => {
+                output::record(wsl_result);
+                Ok(())
+            },
+            Err(error) => {
+                output::record(WslResult::failed(&wsl_conf.name, &error));
+                if !fail_fast {
+                    message!("Could not pull the image of WSL `{}`, going on with the next one: {:#}", &wsl_conf.name, error);
+                }
+                Err(error)
+            }
+        }
+    }, |outcome| fail_fast && outcome.is_err());
+
+    let fail_fast_error = if fail_fast { outcomes.into_iter().find_map(Result::err) } else { None };
+    finish_batch("pull", wsl_name.is_none(), fail_fast_error)
+}
+
+fn pull_wsl_conf(wsl_conf: &WSLConf, runtime: Runtime, dockerwsl_path: &PathBuf) -> Result<WslResult> {
+    let image_url_str = wsl_conf.image.as_str();
+
+    let image_reference = ImageReference::parse(image_url_str)
+        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url_str))?;
+
+    handle_pull_for_image(runtime, dockerwsl_path, &image_reference)
+        .with_context(|| format!("Could not handle pull for image `{}`!", image_url_str))?;
+
+    let digest = resolve_image_digest(runtime, dockerwsl_path, &image_reference, None);
+    lockfile::update_lock_file(&lockfile::path(dockerwsl_path), &wsl_conf.name, image_url_str, digest.as_deref())
+        .with_context(|| format!("Could not pin the image of `{}` in the lock file!", &wsl_conf.name))?;
+
+    let mut wsl_result = WslResult::new(&wsl_conf.name);
+    wsl_result.image = Some(wsl_conf.image.clone());
+    wsl_result.digest = digest;
+
+    Ok(wsl_result)
+}
+
+fn handle_pull_for_image(runtime: Runtime, dockerwsl_path: &PathBuf, image_reference: &ImageReference) -> Result<()> {
+    let image_url = image_reference.to_string();
+    let image_url_str = image_url.as_str();
+
+    match runtime.container_runtime() {
+        Some(container_runtime) if dry_run::is_enabled() => {
+            message!("Would pull image `{}` with `{}`.", image_url_str, container_runtime.binary());
+        },
+        Some(container_runtime) => {
+            determine_login(container_runtime.as_ref(), image_reference.registry_name(), dockerwsl_path)
+                .with_context(|| format!("Error occurred while determining if login is required for pulling docker image `{}`!", image_url_str))?;
+
+            container_runtime.pull(image_url_str)
+                .with_context(|| format!("Could not pull the image {}!", image_url_str))?;
+        },
+        None => {
+            // layers are only downloaded when a WSL VM is created, pulling just checks the image is reachable
+            let mut registry_client = create_registry_client(image_reference.registry_name(), dockerwsl_path)?;
+
+            registry_client.get_manifest(image_reference.repository_path().as_str(), image_reference.manifest_reference())
+                .with_context(|| format!("Could not find image `{}` in its registry!", image_url_str))?;
+
+            message!("Image `{}` is available in its registry!", image_url_str);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the root filesystem of the image to a tar file in `temp_dir`, from the image archive if there is one.
+fn export_image_to_tar(runtime: Runtime, dockerwsl_path: &PathBuf, image_reference: &ImageReference, image_archive: Option<&ImageArchive>, temp_dir: &TempDir) -> Result<PathBuf> {
+    if dry_run::is_enabled() {
+        let tar_path = temp_dir.path().join(format!("{}.tar", image_reference.short_name()));
+        match image_archive {
+            Some(_) => message!("Would export the root filesystem of `{}` from its image archive to `{}`.", image_reference, tar_path.display()),
+            None => message!("Would export the root filesystem of `{}` to `{}`.", image_reference, tar_path.display())
+        }
+
+        return Ok(tar_path);
+    }
+
+    if let Some(archive) = image_archive {
+        return archive.export_to_tar(temp_dir.path());
+    }
+
+    match runtime.container_runtime() {
+        Some(container_runtime) => export_container_image_to_tar(container_runtime.as_ref(), image_reference.to_string().as_str(), temp_dir),
+        None => {
+            let mut registry_client = create_registry_client(image_reference.registry_name(), dockerwsl_path)?;
+
+            image::export_image_to_tar(&mut registry_client, image_reference.repository_path().as_str(), image_reference.manifest_reference(), temp_dir.path())
+        }
+    }
+}
+
+/// `--runtime` wins over `runtime` in .dockerwsl.
+fn get_runtime(runtime_option: Option<Runtime>, dockerwsl_path: &PathBuf) -> Result<Runtime> {
+    let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    Ok(runtime_option.or(dockerwsl_content.runtime).unwrap_or_default())
+}
+
+fn create_registry_client(registry_name_option: Option<&str>, dockerwsl_path: &PathBuf) -> Result<RegistryClient> {
+    let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    let registry_credentials = get_registry_credentials(registry_name_option, &dockerwsl_content.private_registries)?;
+
+    Ok(RegistryClient::new(registry_name_option, registry_credentials))
+}
+
+fn handle_upgrade(upgrade: Upgrade) -> Result<()> {
+    let mut dockerwsl_content = parse_dockerwslconf_file(&upgrade.dockerwsl)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &upgrade.dockerwsl))?;
+
+    let upgrade_wsl = &upgrade.wsl;
+    let runtime = upgrade.runtime.or(dockerwsl_content.runtime).unwrap_or_default();
+    let profile_backend = get_profile_backend(upgrade.no_terminal_profile, &dockerwsl_content);
+    let wt_settings_paths = match profile_backend {
+        ProfileBackend::Settings => get_windows_terminal_settings_paths(&upgrade.wtconfig, &dockerwsl_content.windows_terminal_installs)?,
+        ProfileBackend::Fragment | ProfileBackend::Disabled => vec![]
+    };
+    let default_profile_template = dockerwsl_content.default_windows_terminal_profile.clone();
+    let lock_file_path = lockfile::path(&upgrade.dockerwsl);
+    let lock_file = LockFile::read(&lock_file_path)
+        .with_context(|| format!("Could not read lock file `{}`!", lock_file_path.display()))?;
+    let fail_fast = upgrade.fail_fast || upgrade_wsl.is_some();
+    let mut selected_wsl_confs = vec![];
+
+    for wsl_conf in dockerwsl_content.wsls.iter_mut() {
+        match upgrade_wsl {
+            Some(name) => {
+                if name.ne(&wsl_conf.name) {
+                    debug!("Passed wsl name (`{}`) doesn't match current config entry name, will SKIP it!", &name);
+                    continue;
+                } else {
+                    debug!("Passed wsl name (`{}`) matches current config entry name, will process it!", &name);
+                }
+            },
+            None => { debug!("No wsl name passed to `upgrade`, will upgrade all wsls in the config file!"); }
+        }
+
+        selected_wsl_confs.push(wsl_conf);
+    }
+
+    // the upgrades only meet in the images to pin, settings.json is locked where it is written
+    let pinned_images = Mutex::new(vec![]);
+    let unpinned_wsl_names = Mutex::new(vec![]);
+    let parallel = upgrade.jobs > 1;
+    let outcomes = jobs::run(upgrade.jobs, selected_wsl_confs, |wsl_conf| {
+        output::set_message_prefix(if parallel { Some(wsl_conf.name.as_str()) } else { None });
+
+        let locked_image = if upgrade.locked { lock_file.get(&wsl_conf.name).cloned() } else { None };
+        let wsl_conf_before_upgrade = wsl_conf.clone();
+        let old_tag = ImageReference::parse(&wsl_conf.image).ok().and_then(|image_reference| image_reference.tag);
+
+        let upgrade_result = if upgrade.locked && locked_image.is_none() {
+            Err(anyhow::anyhow!("WSL `{}` is not pinned in lock file `{}`! Run `dragon upgrade` without `--locked` to pin it.", &wsl_conf.name, lock_file_path.display()))
+        } else {
+            upgrade_wsl_conf(wsl_conf, &upgrade, runtime, &wt_settings_paths, &default_profile_template, locked_image.as_ref())
+        };
+
+        match upgrade_result {
+            Ok(digest) => {
+                if locked_image.is_none() {
+                    match &digest {
+                        Some(digest) => pinned_images.lock().unwrap().push(LockedImage { name: wsl_conf.name.clone(), image: wsl_conf.image.clone(), digest: digest.clone() }),
+                        None => {
+                            message!("Could not determine the digest of image `{}`, WSL `{}` is not pinned in `{}`!", &wsl_conf.image, &wsl_conf.name, lock_file_path.display());
+                            unpinned_wsl_names.lock().unwrap().push(wsl_conf.name.clone());
+                        }
+                    }
+                }
+
+                let mut wsl_result = WslResult::new(&wsl_conf.name);
+                wsl_result.image = Some(wsl_conf.image.clone());
+                wsl_result.old_tag = old_tag;
+                wsl_result.new_tag = wsl_conf.latest.clone();
+                wsl_result.vm_name = wsl_conf.latest.as_ref().and_then(|latest_tag| get_wsl_wm_name(&wsl_conf.name, latest_tag).ok());
+                wsl_result.install_path = wsl_conf.history.last().map(|installed_version| installed_version.install_path.clone());
+                wsl_result.profile_guid = Some(wsl_conf.windows_terminal_profile_id.clone());
+                wsl_result.digest = digest;
+                output::record(wsl_result);
+                Ok(wsl_conf.name.clone())
+            },
+            Err(error) => {
+                // the WSL VMs are back to how they were before the upgrade, so is the entry
+                *wsl_conf = wsl_conf_before_upgrade;
+                let error = error.context(format!("Could not upgrade WSL `{}`, it has been left as it was!", &wsl_conf.name));
+                output::record(WslResult::failed(&wsl_conf.name, &error));
+                if !fail_fast {
+                    message!("{:#}", error);
+                    message!("Going on with the next WSL.");
+                }
+                Err(error)
+            }
+        }
+    }, |outcome| fail_fast && outcome.is_err());
+
+    let (upgraded_wsl_names, upgrade_errors): (Vec<_>, Vec<_>) = outcomes.into_iter().partition(Result::is_ok);
+    let upgraded_wsl_names: Vec<String> = upgraded_wsl_names.into_iter().filter_map(Result::ok).collect();
+    let fail_fast_error = if fail_fast { upgrade_errors.into_iter().find_map(Result::err) } else { None };
+
+    // a locked upgrade installs what the lock file says, it never changes it
+    if !upgrade.locked {
+        let unpinned_wsl_names = unpinned_wsl_names.into_inner().unwrap();
+        let unpinned_wsl_names: Vec<&str> = unpinned_wsl_names.iter().map(|wsl_name| wsl_name.as_str()).collect();
+        lockfile::pin_images(&lock_file_path, &pinned_images.into_inner().unwrap(), &unpinned_wsl_names)
+            .with_context(|| format!("Could not write lock file `{}`!", lock_file_path.display()))?;
+    }
+
+    // WSLs upgraded successfully keep their new version, whatever happened to the others
+    let upgraded_wsl_confs: Vec<&WSLConf> = dockerwsl_content.wsls.iter().filter(|wsl_conf| upgraded_wsl_names.contains(&wsl_conf.name)).collect();
+    update_dockerwsl_file(&upgrade.dockerwsl, |current_dockerwsl_content| {
+        replace_wsl_confs(current_dockerwsl_content, &upgraded_wsl_confs);
+        Ok(())
+    }).with_context(|| "An error occurred while writing to the .dockerwsl file the updates from the upgrade subcommand!")?;
+
+    if profile_backend == ProfileBackend::Fragment {
+        update_windows_terminal_fragment(&upgrade.dockerwsl)
+            .with_context(|| "Could not update the Windows Terminal fragment!")?;
+    }
+
+    finish_batch("upgrade", upgrade_wsl.is_none(), fail_fast_error)
+}
+
+/// Upgrades the WSL to its `latest` tag, or to the image pinned in the lock file if `locked_image` is passed. Returns the digest of the installed image.
+fn upgrade_wsl_conf(wsl_conf: &mut WSLConf, upgrade: &Upgrade, runtime: Runtime, wt_settings_paths: &[(WindowsTerminalInstall, PathBuf)], default_profile_template: &Option<ProfileTemplate>, locked_image: Option<&LockedImage>) -> Result<Option<String>> {
+    let image_reference = ImageReference::parse(&wsl_conf.image)
+        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", &wsl_conf.image))?;
+
+    let temp_dir = Builder::new().prefix("dragon").tempdir()?;
+    let image_archive = match &wsl_conf.archive {
+        Some(archive_path) => Some(ImageArchive::open(Path::new(archive_path), temp_dir.path())
+            .with_context(|| format!("Could not read image archive `{}`!", archive_path))?),
+        None => None
+    };
+
+    let (updated_image_reference, latest_tag) = match locked_image {
+        Some(locked_image) => {
+            let mut locked_image_reference = ImageReference::parse(&locked_image.image)
+                .with_context(|| format!("Could not extract Docker image details from URL `{}`!", &locked_image.image))?;
+            locked_image_reference.digest = Some(locked_image.digest.clone());
+
+            if let Some(archive) = &image_archive {
+                if archive.digest.as_ref() != Some(&locked_image.digest) {
+                    return Err(anyhow::anyhow!("The image archive of WSL `{}` doesn't contain the pinned image `{}@{}`!", &wsl_conf.name, &locked_image.image, &locked_image.digest));
+                }
+            }
+
+            let locked_tag = locked_image_reference.version_label();
+            wsl_conf.latest = Some(locked_tag.clone());
+            (locked_image_reference, locked_tag)
+        },
+        None => {
+            // an image archive brings its own tag, the latest property is only used if it doesn't
+            if let Some(archive_image_name) = image_archive.as_ref().and_then(|archive| archive.image_name.as_ref()) {
+                let archive_image_reference = ImageReference::parse(archive_image_name)
+                    .with_context(|| format!("Could not extract Docker image details from URL `{}`!", archive_image_name))?;
+
+                if archive_image_reference.tag.is_some() {
+                    wsl_conf.latest = archive_image_reference.tag;
+                }
+            }
+
+            let latest_tag = wsl_conf.latest.clone()
+                .with_context(|| format!("There is no latest property in .dockerwsl for WSL `{}`! Either add the value manually or use `dragon update`.", &wsl_conf.name))?;
+            (image_reference.with_tag(&latest_tag), latest_tag)
+        }
+    };
+    let updated_image_url = updated_image_reference.to_string();
+
+    if image_archive.is_none() {
+        handle_pull_for_image(runtime, &upgrade.dockerwsl, &updated_image_reference)
+            .with_context(|| format!("Could not handle pull for image `{}`!", updated_image_url.as_str()))?;
+    }
+
+    let wsl_vm_name = get_wsl_wm_name(&wsl_conf.name, &latest_tag)
+        .with_context(|| "Could not compose WSL VM name from WSL name and tag!")?;
+    let wsl_vm_name_str = wsl_vm_name.as_str();
+
+    let tar_path = export_image_to_tar(runtime, &upgrade.dockerwsl, &updated_image_reference, image_archive.as_ref(), &temp_dir)
+        .with_context(|| format!("Could not export docker image `{}` to tar file!", updated_image_url.as_str()))?;
+
+    let base_install_path = PathBuf::from(&wsl_conf.base_install_path);
+    upgrade_wsl_vm_from_tar(wsl_vm_name_str, &tar_path, &base_install_path)
+        .with_context(|| format!("Could not create WSL VM with name `{}`", wsl_vm_name_str))?;
+
+    // only once the WSL VM boots, so a failed import leaves settings.json as it was. The fragment is regenerated once all WSLs are upgraded.
+    for (install, settings_path) in wt_settings_paths.iter() {
+        let profile_templates = get_profile_templates(default_profile_template, &wsl_conf.windows_terminal_profile);
+        create_windows_terminal_profile(settings_path, wsl_conf.windows_terminal_profile_id.as_str(), &wsl_conf.name, &profile_templates)
+            .with_context(|| format!("Could not create Windows Terminal profile in `{}` for `{}`!", settings_path.display(), &wsl_conf.name))?;
+
+        record_windows_terminal_profile_state(wsl_conf, *install, settings_path);
+    }
+
+    // .dockerwsl keeps the tag, the digest is in the history and the lock file
+    let installed_image_url = match locked_image {
+        Some(locked_image) => locked_image.image.clone(),
+        None => updated_image_url.clone()
+    };
+    let digest = resolve_image_digest(runtime, &upgrade.dockerwsl, &updated_image_reference, image_archive.as_ref());
+    record_installed_version(wsl_conf, InstalledVersion::new(installed_image_url.as_str(), &latest_tag, digest.clone(), &base_install_path.join(wsl_vm_name_str)));
+    wsl_conf.image = installed_image_url;
+
+    if !dry_run::is_enabled() {
+        match locked_image {
+            Some(locked_image) => message!("WSL `{}` has been upgraded to the pinned image `{}@{}`!", &wsl_conf.name, &locked_image.image, &locked_image.digest),
+            None => message!("WSL `{}` has been upgraded to tag `{}`!", &wsl_conf.name, &latest_tag)
+        }
+    }
+
+    Ok(digest)
+}
+
+fn handle_rollback(rollback: Rollback) -> Result<()> {
+    let dockerwsl_path = &rollback.dockerwsl;
+    let mut dockerwsl_content = parse_dockerwslconf_file(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+    let runtime = rollback.runtime.or(dockerwsl_content.runtime).unwrap_or_default();
+    let wsl_name = &rollback.wsl;
+
+    let wsl_conf = dockerwsl_content.wsls.iter_mut().find(|wsl| &wsl.name == wsl_name)
+        .with_context(|| format!("Could not find .dockerwsl config with name `{}`!", wsl_name))?;
+
+    let image_reference = ImageReference::parse(&wsl_conf.image)
+        .with_context(|| format!("Could not extract Docker image details from URL `{}`!", &wsl_conf.image))?;
+    let current_tag = image_reference.tag.clone();
+
+    let target_tag = match &rollback.to {
+        Some(tag) => tag.clone(),
+        None => wsl_conf.history.iter().rev()
+            .find(|installed_version| Some(&installed_version.tag) != current_tag.as_ref())
+            .map(|installed_version| installed_version.tag.clone())
+            .with_context(|| format!("There is no previously installed tag in the history of WSL `{}` to roll back to!", wsl_name))?
+    };
+
+    if current_tag.as_ref() == Some(&target_tag) {
+        message!("WSL `{}` is already on tag `{}`!", wsl_name, &target_tag);
+        return Ok(());
+    }
+
+    let target_image_reference = image_reference.with_tag(&target_tag);
+    let wsl_vm_name = get_wsl_wm_name(wsl_name.as_str(), &target_tag)
+        .with_context(|| "Could not compose WSL VM name from WSL name and tag!")?;
+    let wsl_vm_name_str = wsl_vm_name.as_str();
+
+    let wsl_vm_exists_bool = wsl_vm_exists(wsl_vm_name_str)
+        .with_context(|| format!("Could not verify if WSL VM `{}` already exists!", wsl_vm_name_str))?;
+
+    if wsl_vm_exists_bool {
+        debug!("WSL VM `{}` is still installed, only .dockerwsl needs to change.", wsl_vm_name_str);
+    } else {
+        message!("WSL VM `{}` is not installed anymore, re-importing it...", wsl_vm_name_str);
+
+        let installed_version = reimport_wsl_vm(wsl_conf, runtime, dockerwsl_path, &target_image_reference, wsl_vm_name_str)
+            .with_context(|| format!("Could not re-import WSL VM `{}`!", wsl_vm_name_str))?;
+        record_installed_version(wsl_conf, installed_version);
+    }
+
+    wsl_conf.image = target_image_reference.to_string();
+    wsl_conf.latest = Some(target_tag.clone());
+
+    // a mutable tag can have been installed more than once, the last install is what the WSL VM is now
+    let target_digest = wsl_conf.history.iter().rev()
+        .find(|installed_version| installed_version.tag == target_tag)
+        .and_then(|installed_version| installed_version.digest.clone());
+    lockfile::update_lock_file(&lockfile::path(dockerwsl_path), wsl_name, &wsl_conf.image, target_digest.as_deref())
+        .with_context(|| format!("Could not pin the image of `{}` in the lock file!", wsl_name))?;
+
+    let mut wsl_result = WslResult::new(wsl_name);
+    wsl_result.image = Some(wsl_conf.image.clone());
+    wsl_result.old_tag = current_tag;
+    wsl_result.new_tag = Some(target_tag.clone());
+    wsl_result.vm_name = Some(wsl_vm_name.clone());
+    wsl_result.digest = target_digest;
+
+    update_dockerwsl_file(dockerwsl_path, |current_dockerwsl_content| {
+        replace_wsl_confs(current_dockerwsl_content, &[wsl_conf]);
+        Ok(())
+    }).with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+
+    if !dry_run::is_enabled() {
+        message!("WSL `{}` has been rolled back to tag `{}`!", wsl_name, &target_tag);
+    }
+
+    output::record(wsl_result);
+
+    Ok(())
+}
+
+fn handle_config(config: Config) -> Result<()> {
+    match config.command {
+        ConfigCommand::Migrate(migrate) => handle_config_migrate(migrate),
+        ConfigCommand::Validate(validate) => handle_config_validate(validate),
+        ConfigCommand::Show(show) => handle_config_show(show),
+        ConfigCommand::Set(set) => handle_config_set(set),
+        ConfigCommand::Edit(edit) => handle_config_edit(edit)
+    }
+}
+
+fn handle_config_migrate(migrate: ConfigMigrate) -> Result<()> {
+    let dockerwsl_path = &migrate.dockerwsl;
+    let _file_lock = safe_file::lock(dockerwsl_path)?;
+
+    let dockerwsl_text = std::fs::read_to_string(dockerwsl_path)
+        .with_context(|| format!("Could not open .dockerwsl file `{}` for reading!", dockerwsl_path.display()))?;
+    let (dockerwsl_document, version) = schema::parse(&dockerwsl_text)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    if version == schema::CURRENT_VERSION {
+        message!("`{}` already has the current schema version {}, nothing to migrate.", dockerwsl_path.display(), version);
+        return Ok(());
+    }
+
+    let dockerwsl_content: DockerWSLConf = serde_yaml::from_value(dockerwsl_document)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    // a dry run prints the diff when writing
+    if !dry_run::is_enabled() {
+        let migrated_dockerwsl_text = serde_yaml::to_string(&dockerwsl_content)
+            .with_context(|| "Could not serialize the .dockerwsl file!")?;
+
+        message!("Migrating `{}` from schema version {} to {}:", dockerwsl_path.display(), version, schema::CURRENT_VERSION);
+        for line in dry_run::diff_lines(&dockerwsl_text, &migrated_dockerwsl_text).iter() {
+            message!("    {}", line);
+        }
+    }
+
+    write_dockerwsl_file(dockerwsl_path, &dockerwsl_content)
+        .with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+
+    if !dry_run::is_enabled() {
+        message!("`{}` has been migrated to schema version {}, the previous version is kept as a .bak file next to it!", dockerwsl_path.display(), schema::CURRENT_VERSION);
+    }
+
+    Ok(())
+}
+
+fn handle_config_validate(validate: ConfigValidate) -> Result<()> {
+    let dockerwsl_path = &validate.dockerwsl;
+
+    let dockerwsl_text = std::fs::read_to_string(dockerwsl_path)
+        .with_context(|| format!("Could not open .dockerwsl file `{}` for reading!", dockerwsl_path.display()))?;
+    let config_problems = check_dockerwsl_text(&dockerwsl_text);
+
+    if config_problems.errors.is_empty() && config_problems.warnings.is_empty() {
+        message!("`{}` is valid.", dockerwsl_path.display());
+        return Ok(());
+    }
+
+    message!("Found {} error(s) and {} warning(s) in `{}`:", config_problems.errors.len(), config_problems.warnings.len(), dockerwsl_path.display());
+    print_config_problems(&config_problems);
+
+    if !config_problems.errors.is_empty() {
+        return Err(anyhow::anyhow!("`{}` is not valid!", dockerwsl_path.display()));
+    }
+
+    Ok(())
+}
+
+fn handle_config_show(show: ConfigShow) -> Result<()> {
+    let dockerwsl_path = &show.dockerwsl;
+
+    let dockerwsl_text = std::fs::read_to_string(dockerwsl_path)
+        .with_context(|| format!("Could not open .dockerwsl file `{}` for reading!", dockerwsl_path.display()))?;
+    let (mut dockerwsl_document, _version) = schema::parse(&dockerwsl_text)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    config_edit::redact_secrets(&mut dockerwsl_document);
+
+    let shown_dockerwsl_text = serde_yaml::to_string(&dockerwsl_document)
+        .with_context(|| "Could not serialize the .dockerwsl file!")?;
+    message!("{}", shown_dockerwsl_text.trim_end());
+
+    Ok(())
+}
+
+fn handle_config_set(set: ConfigSet) -> Result<()> {
+    let dockerwsl_path = &set.dockerwsl;
+    let _file_lock = safe_file::lock(dockerwsl_path)?;
+
+    let dockerwsl_text = if dockerwsl_path.exists() {
+        std::fs::read_to_string(dockerwsl_path)
+            .with_context(|| format!("Could not open .dockerwsl file `{}` for reading!", dockerwsl_path.display()))?
+    } else {
+        String::new()
+    };
+    let (mut dockerwsl_document, _version) = schema::parse(&dockerwsl_text)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    config_edit::set_value(&mut dockerwsl_document, &set.path, &set.value)
+        .with_context(|| format!("Could not set `{}` in `{}`!", &set.path, dockerwsl_path.display()))?;
+
+    let changed_dockerwsl_text = serde_yaml::to_string(&dockerwsl_document)
+        .with_context(|| "Could not serialize the .dockerwsl file!")?;
+    let config_problems = check_dockerwsl_changes(&dockerwsl_text, &changed_dockerwsl_text);
+
+    if !config_problems.errors.is_empty() {
+        message!("Setting `{}` would leave `{}` with {} error(s):", &set.path, dockerwsl_path.display(), config_problems.errors.len());
+        print_config_problems(&config_problems);
+        return Err(anyhow::anyhow!("`{}` has been left as it is!", dockerwsl_path.display()));
+    }
+    print_config_problems(&config_problems);
+
+    write_dockerwsl_text(dockerwsl_path, &changed_dockerwsl_text)
+        .with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+
+    if !dry_run::is_enabled() {
+        message!("`{}` has been set in `{}`!", &set.path, dockerwsl_path.display());
+    }
+
+    Ok(())
+}
+
+fn handle_config_edit(edit: ConfigEdit) -> Result<()> {
+    let dockerwsl_path = &edit.dockerwsl;
+    // nothing else may change .dockerwsl while it is open in the editor
+    let _file_lock = safe_file::lock(dockerwsl_path)?;
+
+    let dockerwsl_text = if dockerwsl_path.exists() {
+        std::fs::read_to_string(dockerwsl_path)
+            .with_context(|| format!("Could not open .dockerwsl file `{}` for reading!", dockerwsl_path.display()))?
+    } else {
+        serde_yaml::to_string(&get_dockerwsl_content(dockerwsl_path)?)
+            .with_context(|| "Could not serialize the .dockerwsl file!")?
+    };
+
+    let temp_dir = Builder::new().prefix("dragon").tempdir()?;
+    let edited_file_path = temp_dir.path().join("dockerwsl.yaml");
+    std::fs::write(&edited_file_path, &dockerwsl_text)
+        .with_context(|| format!("Could not write `{}`!", edited_file_path.display()))?;
+
+    loop {
+        open_editor(&edited_file_path)?;
+
+        let edited_dockerwsl_text = std::fs::read_to_string(&edited_file_path)
+            .with_context(|| format!("Could not read `{}`!", edited_file_path.display()))?;
+        if edited_dockerwsl_text == dockerwsl_text {
+            message!("`{}` has not been changed.", dockerwsl_path.display());
+            return Ok(());
+        }
 
-fn docker_export(docker_container_id: &str, tar_file_path: &PathBuf) -> Result<()> {
-    let tar_file_path_str = tar_file_path.to_str()
-        .with_context(|| format!("Could not convert path `{}` to &str!", tar_file_path.display()))?;
+        let config_problems = check_dockerwsl_changes(&dockerwsl_text, &edited_dockerwsl_text);
+        if config_problems.errors.is_empty() {
+            print_config_problems(&config_problems);
 
-    let mut docker_container_export_command = Command::new(r#"docker"#);
-    docker_container_export_command.args(&["container", "export"]);
-    docker_container_export_command.args(&["-o", tar_file_path_str]);
-    docker_container_export_command.arg(docker_container_id);
+            write_dockerwsl_text(dockerwsl_path, &edited_dockerwsl_text)
+                .with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
 
-    let docker_container_export_command_status = docker_container_export_command.status()
-        .with_context(|| format!("`docker container export -o {} {}` failed!", tar_file_path_str, docker_container_id))?;
+            if !dry_run::is_enabled() {
+                message!("`{}` has been saved, the previous version is kept as a .bak file next to it!", dockerwsl_path.display());
+            }
+            return Ok(());
+        }
 
-    if !docker_container_export_command_status.success() {
-        return Err(anyhow::anyhow!("Could not `docker container export -o {} {}`!", tar_file_path_str, docker_container_id));
-    }
+        message!("The changes to `{}` have {} error(s):", dockerwsl_path.display(), config_problems.errors.len());
+        print_config_problems(&config_problems);
 
-    Ok(())
+        if !ask_to_edit_again()? {
+            return Err(anyhow::anyhow!("`{}` has been left as it is, the changes have been discarded!", dockerwsl_path.display()));
+        }
+    }
 }
 
-fn create_dockerwsl_config_entry(dockerwsl_path: &PathBuf, image_url: &str, wsl_name: &str, wt_profile_id: &str, base_install_path: &PathBuf, latest_tag_str: &str) -> Result<()> {
-    let mut dockerwsl_content = get_dockerwsl_content(&dockerwsl_path)
-        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
+/// Runs $EDITOR on the file and waits for it to be closed. Without $EDITOR, notepad is used on Windows and vi elsewhere.
+fn open_editor(file_path: &Path) -> Result<()> {
+    let editor = std::env::var("EDITOR").ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() });
 
-    let existing_dockerwsl = dockerwsl_content.wsls.iter().find(|wsl| wsl.name == wsl_name);
+    // editors like VS Code need arguments, e.g. `code --wait`
+    let mut editor_parts = editor.split_whitespace();
+    let mut editor_command = Command::new(editor_parts.next().unwrap());
+    editor_command.args(editor_parts).arg(file_path);
 
-    if existing_dockerwsl.is_some() {
-        return Err(anyhow::anyhow!("There is already a dockerwsl config with the name `{}`!", wsl_name));
+    let editor_status = editor_command.status()
+        .with_context(|| format!("Could not start editor `{}`, set $EDITOR to the one to use!", &editor))?;
+
+    if !editor_status.success() {
+        return Err(anyhow::anyhow!("Editor `{}` failed with {}!", &editor, editor_status));
     }
 
-    let base_install_path_str = base_install_path.to_str().with_context(|| format!("Could not convert install path to &str!"))?;
+    Ok(())
+}
 
-    let wslconf = WSLConf {
-        name: wsl_name.to_string(),
-        image: image_url.to_string(),
-        latest: Some(latest_tag_str.to_string()),
-        base_install_path: format!("{}", base_install_path_str),
-        windows_terminal_profile_id: wt_profile_id.to_string()
-    };
+/// Asks whether to fix the errors in the editor. Only asked on a terminal, otherwise the changes are discarded.
+fn ask_to_edit_again() -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
 
-    dockerwsl_content.wsls.insert(0, wslconf);
+    eprint!("Edit again? [Y/n] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)
+        .with_context(|| "Could not read the answer!")?;
 
-    write_dockerwsl_file(dockerwsl_path, &dockerwsl_content)
-        .with_context(|| format!("Could not write `.dockerwsl` file `{:#?}`!", dockerwsl_path))?;
+    Ok(!answer.trim().eq_ignore_ascii_case("n") && !answer.trim().eq_ignore_ascii_case("no"))
+}
 
-    println!("WSL config for `{}` has been added to the .dockerwsl file!", wsl_name);
+/// What is wrong with a .dockerwsl file. Errors stop dragon from working with it, warnings may only apply to this machine,
+/// like install paths or secret files that are somewhere else on another machine.
+#[derive(Debug, Default)]
+struct ConfigProblems {
+    errors: Vec<String>,
+    warnings: Vec<String>
+}
 
-    Ok(())
+fn print_config_problems(config_problems: &ConfigProblems) {
+    for error in config_problems.errors.iter() {
+        message!("    ERROR: {}", error);
+    }
+    for warning in config_problems.warnings.iter() {
+        message!("    WARNING: {}", warning);
+    }
 }
 
-fn extract_generic_image_details(image_url: &str) -> Result<(Option<String>, String, Option<String>)> {
-    let regex = regex::Regex::new(r"^(?:.*?(.+?)/)?([^:]+)(?::(.+))?$").unwrap();
-    let image_regex_captures = regex.captures(image_url)
-        .with_context(|| format!("Docker image property does not have the expected format `[registry/]repository[:tag]`!"))?;
-    
-    let registry_name = image_regex_captures.get(1).map_or(None, |m| Option::from(m.as_str().to_string()));
-    let repository_name = image_regex_captures.get(2)
-        .with_context(|| format!("Could not extract repository name from Docker image URL `{}`!", image_url))?
-        .as_str();
-    let tag = image_regex_captures.get(3).map_or(None, |m| Option::from(m.as_str().to_string()));
+fn check_dockerwsl_text(dockerwsl_text: &str) -> ConfigProblems {
+    match parse_dockerwsl_text(dockerwsl_text) {
+        Ok(dockerwsl_content) => check_dockerwsl_content(&dockerwsl_content),
+        Err(error) => ConfigProblems { errors: vec![format!("{:#}", error)], warnings: vec![] }
+    }
+}
+
+/// Checks .dockerwsl after a change, leaving out the errors it had before. Those don't stop the change, so a broken file
+/// can be fixed one step at a time.
+fn check_dockerwsl_changes(dockerwsl_text: &str, changed_dockerwsl_text: &str) -> ConfigProblems {
+    let previous_errors = check_dockerwsl_text(dockerwsl_text).errors;
 
-    Ok((registry_name, repository_name.to_string(), tag))
+    let mut config_problems = check_dockerwsl_text(changed_dockerwsl_text);
+    config_problems.errors.retain(|error| !previous_errors.contains(error));
+
+    config_problems
 }
 
-fn handle_pull(pull: Pull) -> Result<()> {
-    let dockerwsl_path = &pull.dockerwsl;
-    let wsl_name = &pull.wsl;
-    
-    let dockerwsl_content = parse_dockerwslconf_file(&dockerwsl_path)
-        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &dockerwsl_path))?;
+/// Checks what deserializing .dockerwsl doesn't, without talking to registries or running credential helpers.
+fn check_dockerwsl_content(dockerwsl_content: &DockerWSLConf) -> ConfigProblems {
+    let mut config_problems = ConfigProblems::default();
 
-    for wsl_conf in dockerwsl_content.wsls.iter() {
-        match wsl_name {
-            Some(name) => {
-                if name.ne(&wsl_conf.name) {
-                    debug!("Passed wsl name (`{}`) doesn't match current config entry name, will SKIP it!", &name);
-                    continue;
-                } else {
-                    debug!("Passed wsl name (`{}`) matches current config entry name, will process it!", &name);
+    if let Some(default_base_wsl_install_path) = &dockerwsl_content.default_base_wsl_install_path {
+        if !Path::new(default_base_wsl_install_path).exists() {
+            config_problems.warnings.push(format!("`default_base_wsl_install_path` `{}` doesn't exist!", default_base_wsl_install_path));
+        }
+    }
+
+    for (index, wsl_conf) in dockerwsl_content.wsls.iter().enumerate() {
+        let previous_wsl_confs = &dockerwsl_content.wsls[..index];
+
+        if previous_wsl_confs.iter().any(|previous_wsl_conf| previous_wsl_conf.name == wsl_conf.name) {
+            config_problems.errors.push(format!("WSL `{}` is configured more than once!", &wsl_conf.name));
+        }
+
+        match ImageReference::parse(&wsl_conf.image) {
+            Ok(image_reference) => {
+                if let Some(registry_name_str) = image_reference.registry_name() {
+                    if !dockerwsl_content.private_registries.iter().any(|reg| reg.name.as_str() == registry_name_str) {
+                        config_problems.warnings.push(format!("WSL `{}`: registry `{}` has no entry in `private_registries`, pulling `{}` only works if the registry is public!", &wsl_conf.name, registry_name_str, &wsl_conf.image));
+                    }
                 }
             },
-            None => { debug!("No wsl name passed to `upgrade`, will upgrade all wsls in the config file!"); }
+            Err(error) => config_problems.errors.push(format!("WSL `{}`: {:#}", &wsl_conf.name, error))
         }
 
-        let image_url_str = wsl_conf.image.as_str();
+        if uuid::Uuid::parse_str(&wsl_conf.windows_terminal_profile_id).is_err() {
+            config_problems.errors.push(format!("WSL `{}`: `windows_terminal_profile_id` `{}` is not a valid GUID, like `c6eaf9f4-32a7-4fdc-b5cf-066e8a4b1e40`!", &wsl_conf.name, &wsl_conf.windows_terminal_profile_id));
+        } else if previous_wsl_confs.iter().any(|previous_wsl_conf| previous_wsl_conf.windows_terminal_profile_id.eq_ignore_ascii_case(&wsl_conf.windows_terminal_profile_id)) {
+            config_problems.errors.push(format!("WSL `{}`: `windows_terminal_profile_id` `{}` is used by another WSL too!", &wsl_conf.name, &wsl_conf.windows_terminal_profile_id));
+        }
+
+        if !Path::new(&wsl_conf.base_install_path).exists() {
+            config_problems.warnings.push(format!("WSL `{}`: `base_install_path` `{}` doesn't exist!", &wsl_conf.name, &wsl_conf.base_install_path));
+        }
+    }
+
+    for private_registry in dockerwsl_content.private_registries.iter() {
+        private_registry.check_credentials(&mut config_problems);
+    }
+
+    config_problems
+}
 
-        let (registry_name, _repository_name, _tag) = extract_generic_image_details(image_url_str)
-            .with_context(|| format!("Could not extract Docker image details from URL `{}`!", image_url_str))?;
+fn reimport_wsl_vm(wsl_conf: &WSLConf, runtime: Runtime, dockerwsl_path: &PathBuf, image_reference: &ImageReference, wsl_vm_name_str: &str) -> Result<InstalledVersion> {
+    let image_url = image_reference.to_string();
+    let image_url_str = image_url.as_str();
+    let tag = image_reference.version_label();
+    let tag = tag.as_str();
+    let temp_dir = Builder::new().prefix("dragon").tempdir()?;
+    let image_archive = match &wsl_conf.archive {
+        Some(archive_path) => Some(ImageArchive::open(Path::new(archive_path), temp_dir.path())
+            .with_context(|| format!("Could not read image archive `{}`!", archive_path))?),
+        None => None
+    };
+
+    // an image archive only holds a single tag, which has to be the one rolled back to
+    if let Some(archive_image_name) = image_archive.as_ref().and_then(|archive| archive.image_name.as_ref()) {
+        let archive_image_reference = ImageReference::parse(archive_image_name)
+            .with_context(|| format!("Could not extract Docker image details from URL `{}`!", archive_image_name))?;
+
+        if archive_image_reference.tag.as_deref().is_some_and(|archive_tag| archive_tag != tag) {
+            return Err(anyhow::anyhow!("The image archive of WSL `{}` contains `{}`, not tag `{}`!", &wsl_conf.name, archive_image_name, tag));
+        }
+    }
 
-        handle_pull_for_image(registry_name, dockerwsl_path, image_url_str)
+    if image_archive.is_none() {
+        handle_pull_for_image(runtime, dockerwsl_path, image_reference)
             .with_context(|| format!("Could not handle pull for image `{}`!", image_url_str))?;
     }
-   
-    Ok(())
+
+    let tar_path = export_image_to_tar(runtime, dockerwsl_path, image_reference, image_archive.as_ref(), &temp_dir)
+        .with_context(|| format!("Could not export docker image `{}` to tar file!", image_url_str))?;
+
+    let base_install_path = PathBuf::from(&wsl_conf.base_install_path);
+    import_and_verify_wsl_vm(wsl_vm_name_str, &tar_path, &base_install_path)?;
+
+    if !dry_run::is_enabled() {
+        message!("WSL VM `{}` has been added!", wsl_vm_name_str);
+    }
+
+    let digest = resolve_image_digest(runtime, dockerwsl_path, image_reference, image_archive.as_ref());
+    Ok(InstalledVersion::new(image_url_str, tag, digest, &base_install_path.join(wsl_vm_name_str)))
 }
 
-fn handle_pull_for_image(registry_name_option:Option<String>, dockerwsl_path:&PathBuf, image_url_str: &str) -> Result<()> {
-    determine_login(registry_name_option, dockerwsl_path)
-        .with_context(|| format!("Error occurred while determining if login is required for pulling docker image `{}`!", image_url_str))?;
+/// Replaces an earlier install of the same tag, so the history has one entry per tag.
+fn record_installed_version(wsl_conf: &mut WSLConf, installed_version: InstalledVersion) {
+    wsl_conf.history.retain(|existing_version| existing_version.tag != installed_version.tag);
+    wsl_conf.history.push(installed_version);
+}
 
-    pull_image_tag(image_url_str)
-        .with_context(|| format!("Could not pull the image {}!", image_url_str))?;
-    
-    Ok(())
+/// The manifest digest of the image that was just installed. Only recorded for reference, so failing to get it is not an error.
+fn resolve_image_digest(runtime: Runtime, dockerwsl_path: &PathBuf, image_reference: &ImageReference, image_archive: Option<&ImageArchive>) -> Option<String> {
+    if let Some(archive) = image_archive {
+        return archive.digest.clone();
+    }
+    if image_reference.digest.is_some() {
+        return image_reference.digest.clone();
+    }
+
+    let digest_result = match runtime.container_runtime() {
+        Some(container_runtime) => container_runtime.image_digest(image_reference.to_string().as_str()),
+        None => create_registry_client(image_reference.registry_name(), dockerwsl_path).and_then(|mut registry_client| {
+            registry_client.get_manifest_digest(image_reference.repository_path().as_str(), image_reference.manifest_reference()).map(Some)
+        })
+    };
+
+    match digest_result {
+        Ok(digest) => digest,
+        Err(error) => {
+            debug!("Could not determine the digest of image `{}`: {:#}", image_reference, error);
+            None
+        }
+    }
 }
 
-fn handle_upgrade(upgrade: Upgrade) -> Result<()> {
-    let mut dockerwsl_content = parse_dockerwslconf_file(&upgrade.dockerwsl)
-        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", &upgrade.dockerwsl))?;
+/// Imports the tar as `wsl_vm_name_str` without ever leaving the user without a working WSL VM.
+///
+/// If no WSL VM with that name exists (a new tag), the previous WSL VM isn't touched and the new one is simply removed again
+/// if it doesn't boot. If it exists (same tag), the tar is first imported and booted under a temporary name, then the
+/// existing WSL VM is backed up with `wsl --export` and replaced. If the replacement doesn't boot, the backup is restored.
+fn upgrade_wsl_vm_from_tar(wsl_vm_name_str: &str, tar_path: &Path, base_install_path: &Path) -> Result<()> {
+    let wsl_vm_exists_bool = wsl_vm_exists(wsl_vm_name_str)
+        .with_context(|| format!("Could not verify if WSL VM `{}` already exists!", wsl_vm_name_str))?;
 
-    let upgrade_wsl = &upgrade.wsl;
+    if dry_run::is_enabled() && wsl_vm_exists_bool {
+        message!("Would replace WSL VM `{}`: import `{}` as `{}-dragon-upgrade` to check it boots, back up `{}` to `{}`, unregister it and import `{}` into `{}`.",
+            wsl_vm_name_str, tar_path.display(), wsl_vm_name_str, wsl_vm_name_str, base_install_path.join(format!("{}-backup.tar", wsl_vm_name_str)).display(),
+            tar_path.display(), base_install_path.join(wsl_vm_name_str).display());
+        return Ok(());
+    }
 
-    for wsl_conf in dockerwsl_content.wsls.iter_mut() {
-        match upgrade_wsl {
-            Some(name) => {
-                if name.ne(&wsl_conf.name) {
-                    debug!("Passed wsl name (`{}`) doesn't match current config entry name, will SKIP it!", &name);
-                    continue;
-                } else {
-                    debug!("Passed wsl name (`{}`) matches current config entry name, will process it!", &name);
-                }
-            },
-            None => { debug!("No wsl name passed to `upgrade`, will upgrade all wsls in the config file!"); }
+    if !wsl_vm_exists_bool {
+        import_and_verify_wsl_vm(wsl_vm_name_str, tar_path, base_install_path)?;
+        if !dry_run::is_enabled() {
+            message!("WSL VM `{}` has been added!", wsl_vm_name_str);
         }
+        return Ok(());
+    }
 
-        let (registry_name, repository_name, _tag) = extract_generic_image_details(&wsl_conf.image)
-            .with_context(|| format!("Could not extract Docker image details from URL `{}`!", &wsl_conf.image))?;
-        let registry_name_option_clone = registry_name.clone();
+    let temp_wsl_vm_name = format!("{}-dragon-upgrade", wsl_vm_name_str);
+    import_and_verify_wsl_vm(&temp_wsl_vm_name, tar_path, base_install_path)
+        .with_context(|| format!("The new version of WSL VM `{}` doesn't work, the existing one has been kept!", wsl_vm_name_str))?;
+    discard_wsl_vm(&temp_wsl_vm_name, base_install_path)
+        .with_context(|| format!("Could not remove temporary WSL VM `{}`!", &temp_wsl_vm_name))?;
 
-        if wsl_conf.latest.is_none() {
-            return Err(anyhow::anyhow!("There is no latest property in .dockerwsl for WSL `{}`! Either add the value manually or for images in ACR use `dragon update`.", &wsl_conf.name));
-        } 
+    let backup_tar_path = base_install_path.join(format!("{}-backup.tar", wsl_vm_name_str));
+    export_wsl_vm(wsl_vm_name_str, &backup_tar_path)
+        .with_context(|| format!("Could not back up WSL VM `{}`, it has been kept!", wsl_vm_name_str))?;
+    delete_wsl_vm(wsl_vm_name_str)
+        .with_context(|| format!("Could not delete existing WSL VM `{}`!", wsl_vm_name_str))?;
 
-        let latest_tag = wsl_conf.latest.as_ref().unwrap();
-        let updated_image_url = update_image_url(registry_name, &repository_name, latest_tag)
-            .with_context(|| format!("Could not update image URL `{}` with the latest tag `{}`!", &wsl_conf.image, latest_tag))?;
-        
-        handle_pull_for_image(registry_name_option_clone, &upgrade.dockerwsl, updated_image_url.as_str())
-            .with_context(|| format!("Could not handle pull for image `{}`!", updated_image_url.as_str()))?;
+    if let Err(error) = import_and_verify_wsl_vm(wsl_vm_name_str, tar_path, base_install_path) {
+        message!("Replacing WSL VM `{}` failed, restoring it from its backup...", wsl_vm_name_str);
 
-        let wsl_vm_name = get_wsl_wm_name(repository_name.as_str(), latest_tag)
-            .with_context(|| format!("Could not compose WSL VM name from WSL name and tag!"))?;
-        let wsl_vm_name_str = wsl_vm_name.as_str();
+        import_wsl_vm(wsl_vm_name_str, &backup_tar_path, base_install_path)
+            .with_context(|| format!("Could not restore WSL VM `{}`! Its backup has been kept at `{}`.", wsl_vm_name_str, backup_tar_path.display()))?;
+        std::fs::remove_file(&backup_tar_path)
+            .with_context(|| format!("Could not delete backup `{}`!", backup_tar_path.display()))?;
 
-        let temp_dir = Builder::new().prefix("dragon").tempdir()?;
-        let tar_path = export_docker_image_to_tar(updated_image_url.as_str(), &temp_dir)
-            .with_context(|| format!("Could not export docker image `{}` to tar file!", updated_image_url.as_str()))?;
+        return Err(error.context(format!("WSL VM `{}` has been restored to its previous state!", wsl_vm_name_str)));
+    }
 
-        create_wsl_vm_from_tar(wsl_vm_name_str, &tar_path, &PathBuf::from(&wsl_conf.base_install_path))
-            .with_context(|| format!("Could not create WSL VM with name `{}`", wsl_vm_name_str))?;
+    std::fs::remove_file(&backup_tar_path)
+        .with_context(|| format!("Could not delete backup `{}`!", backup_tar_path.display()))?;
 
-        create_windows_terminal_profile(&upgrade.wtconfig, wsl_conf.windows_terminal_profile_id.as_str(), &wsl_conf.name)
-            .with_context(|| format!("Could not create Windows Terminal profile in settings.json for `{}`!", &wsl_conf.name))?;
+    message!("WSL VM `{}` has been replaced!", wsl_vm_name_str);
 
-        wsl_conf.image = updated_image_url;
+    Ok(())
+}
 
-        println!("WSL `{}` has been upgraded to tag `{}`!", &wsl_conf.name, latest_tag);
+/// Imports the tar and boots the new WSL VM once. If it doesn't boot, it is removed again.
+fn import_and_verify_wsl_vm(wsl_vm_name_str: &str, tar_path: &Path, base_install_path: &Path) -> Result<()> {
+    if dry_run::is_enabled() {
+        message!("Would import WSL VM `{}` from `{}` into `{}` and check it boots.", wsl_vm_name_str, tar_path.display(), base_install_path.join(wsl_vm_name_str).display());
+        return Ok(());
     }
 
-    write_dockerwsl_file(&upgrade.dockerwsl, &dockerwsl_content)
-        .with_context(|| format!("An error occurred while writing to the .dockerwsl file the updates from the pull subcommand!"))?;
+    import_wsl_vm(wsl_vm_name_str, tar_path, base_install_path)
+        .with_context(|| format!("Could not import WSL VM `{}`!", wsl_vm_name_str))?;
+
+    if let Err(error) = verify_wsl_vm_boots(wsl_vm_name_str) {
+        discard_wsl_vm(wsl_vm_name_str, base_install_path)
+            .with_context(|| format!("Could not remove WSL VM `{}` that doesn't boot!", wsl_vm_name_str))?;
+
+        return Err(error);
+    }
 
-    
     Ok(())
 }
 
-fn update_image_url(registry_name_option: Option<String>, repository_name_str:&String, latest_tag_str: &str) -> Result<String> {
-    if registry_name_option.is_none() {
-        return Ok(format!("{}:{}", repository_name_str, latest_tag_str));
-    } else {
-        let registry_name = registry_name_option.clone().unwrap();
-        return Ok(format!("{}/{}:{}", registry_name, repository_name_str, latest_tag_str));
+fn verify_wsl_vm_boots(wsl_vm_name_str: &str) -> Result<()> {
+    let mut wsl_exec_command = Command::new(r#"wsl"#);
+    wsl_exec_command.args(["-d", wsl_vm_name_str, "--exec", "true"]);
+
+    let wsl_exec_command_status = output::spawn_child(&mut wsl_exec_command)
+        .and_then(output::ChildProcess::wait)
+        .with_context(|| format!("`wsl -d {} --exec true` failed!", wsl_vm_name_str))?;
+
+    let mut wsl_terminate_command = Command::new(r#"wsl"#);
+    wsl_terminate_command.args(["--terminate", wsl_vm_name_str]);
+    if let Err(error) = wsl_terminate_command.output() {
+        debug!("Could not terminate WSL VM `{}`: {}", wsl_vm_name_str, error);
+    }
+
+    if !wsl_exec_command_status.success() {
+        return Err(anyhow::anyhow!("WSL VM `{}` doesn't boot!", wsl_vm_name_str));
+    }
+
+    Ok(())
+}
+
+fn export_wsl_vm(wsl_vm_name_str: &str, tar_path: &Path) -> Result<()> {
+    let mut wsl_export_command = Command::new(r#"wsl"#);
+    wsl_export_command.args(["--export", wsl_vm_name_str]);
+    wsl_export_command.arg(tar_path);
+
+    let mut progress = Progress::start(&format!("Exporting WSL VM `{}`", wsl_vm_name_str), None);
+    let wsl_export_command_status = output::spawn_child(&mut wsl_export_command)
+        .and_then(|wsl_export_process| progress::wait_for_child(wsl_export_process, &mut progress, Some(tar_path)))
+        .with_context(|| format!("`wsl --export {}` failed!", wsl_vm_name_str))?;
+
+    if !wsl_export_command_status.success() {
+        return Err(anyhow::anyhow!("Could not export `{}` WSL VM!", wsl_vm_name_str));
     }
+    progress.finish();
+
+    Ok(())
+}
+
+/// Unregisters the WSL VM and deletes its install folder.
+fn discard_wsl_vm(wsl_vm_name_str: &str, base_install_path: &Path) -> Result<()> {
+    delete_wsl_vm(wsl_vm_name_str)?;
+    delete_wsl_install_folder(&base_install_path.join(wsl_vm_name_str))
 }
 
 fn delete_wsl_vm(wsl_vm_name_str: &str) -> Result<()> {
+    if dry_run::is_enabled() {
+        message!("Would unregister WSL VM `{}`.", wsl_vm_name_str);
+        return Ok(());
+    }
+
     let mut wsl_unregister_command = Command::new(r#"wsl"#);
     wsl_unregister_command.args(&["--unregister", wsl_vm_name_str]);
 
-    let wsl_unregister_command_status = wsl_unregister_command.status()
+    let wsl_unregister_command_status = output::spawn_child(&mut wsl_unregister_command)
+        .and_then(output::ChildProcess::wait)
         .with_context(|| format!("`wsl --unregister {}` failed!", wsl_vm_name_str))?;
 
     if !wsl_unregister_command_status.success() {
@@ -676,7 +2458,7 @@ fn delete_wsl_vm(wsl_vm_name_str: &str) -> Result<()> {
     Ok(())
 }
 
-fn create_wsl_vm_from_tar(wsl_vm_name_str: &str, tar_path: &PathBuf, base_install_path: &PathBuf) -> Result<()> {
+fn create_wsl_vm_from_tar(wsl_vm_name_str: &str, tar_path: &Path, base_install_path: &Path) -> Result<()> {
     let wsl_wm_exists_bool = wsl_vm_exists(wsl_vm_name_str)
         .with_context(|| format!("Could not verify if WSL VM `{}` already exists!", wsl_vm_name_str))?;
 
@@ -685,6 +2467,19 @@ fn create_wsl_vm_from_tar(wsl_vm_name_str: &str, tar_path: &PathBuf, base_instal
             .with_context(|| format!("Could not existing WSL VM `{}`!", wsl_vm_name_str))?;
     }
 
+    if dry_run::is_enabled() {
+        message!("Would import WSL VM `{}` from `{}` into `{}`.", wsl_vm_name_str, tar_path.display(), base_install_path.join(wsl_vm_name_str).display());
+        return Ok(());
+    }
+
+    import_wsl_vm(wsl_vm_name_str, tar_path, base_install_path)?;
+
+    message!("WSL VM `{}` has been added!", wsl_vm_name_str);
+
+    Ok(())
+}
+
+fn import_wsl_vm(wsl_vm_name_str: &str, tar_path: &Path, base_install_path: &Path) -> Result<()> {
     let tar_path_str = tar_path.to_str()
         .with_context(|| format!("Could not convert path `{}` to &str!", tar_path.display()))?;
     
@@ -700,139 +2495,294 @@ fn create_wsl_vm_from_tar(wsl_vm_name_str: &str, tar_path: &PathBuf, base_instal
     wsl_import_command.arg(wsl_vm_name_str);
     wsl_import_command.arg(install_path_str);
     wsl_import_command.arg(tar_path_str);
-    wsl_import_command.args(&["--version", "2"]);
+    wsl_import_command.args(["--version", "2"]);
 
-    let wsl_import_command_status = wsl_import_command.status()
+    let mut progress = Progress::start(&format!("Importing WSL VM `{}`", wsl_vm_name_str), None);
+    let wsl_import_command_status = output::spawn_child(&mut wsl_import_command)
+        .and_then(|wsl_import_process| progress::wait_for_child(wsl_import_process, &mut progress, None))
         .with_context(|| format!("`wsl --import {}` failed!", wsl_vm_name_str))?;
 
     if !wsl_import_command_status.success() {
         return Err(anyhow::anyhow!("Could not import `{}` WSL VM!", wsl_vm_name_str));
     }
-
-    println!("WSL VM `{}` has been added!", wsl_vm_name_str);
-
+    progress.finish();
 
     Ok(())
 }
 
 
-fn create_windows_terminal_profile(windows_terminal_config_path: &PathBuf, wt_profile_guid: &str, wsl_name: &str) -> Result<()> {
-    let mut wt_config_content = parse_json_file_without_comments(windows_terminal_config_path)
+/// Path of the profiles array in the Windows Terminal settings.json file.
+const WT_PROFILES_LIST_PATH: &[&str] = &["profiles", "list"];
+
+
+/// Adds the profile of a WSL, or if it already exists, brings the settings from the profile templates up to date.
+/// Settings of an existing profile that don't come from the profile templates are left as they are.
+fn create_windows_terminal_profile(windows_terminal_config_path: &PathBuf, wt_profile_guid: &str, wsl_name: &str, profile_templates: &[&ProfileTemplate]) -> Result<()> {
+    let _file_lock = safe_file::lock(windows_terminal_config_path)?;
+    let mut wt_config_document = parse_jsonc_file(windows_terminal_config_path)
         .with_context(|| format!("Could not parse Window Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
-    
-    let wt_profiles = wt_config_content.get_mut("profiles")
-        .with_context(|| format!("Windows Terminal settings file `{:#?}` doesn't have a `profiles` property!", windows_terminal_config_path))?;
-  
-    let wt_profiles_list = wt_profiles.get_mut("list")
-        .with_context(|| format!("Windows Terminal settings file `{:#?}` doesn't have a `profiles.list` property!", windows_terminal_config_path))?;
 
-    let wt_profiles_list_array = wt_profiles_list.as_array_mut()
+    let wt_profiles_list_array = wt_config_document.array_elements(WT_PROFILES_LIST_PATH)
         .with_context(|| format!("Syntax incorrect for `profiles.list` array property in Windows Terminal settings file `{:#?}`", windows_terminal_config_path))?;
-    
-    let wt_profile_guid_with_braces = format!("{{{ }}}", wt_profile_guid);
-
-    let wt_profiles_list_array_filter = |profile: &&mut serde_json::Value| {
-        let profile_object = profile.as_object();
-        match profile_object {
-            Some(p_obj) => {
-                let guid = p_obj.get("guid");
-                match guid {
-                    Some(guid_value) => {
-                        if guid_value.is_string() && guid_value.as_str().unwrap() == wt_profile_guid_with_braces {
-                            return true;
-                        } else {
-                            return false;
-                        }
-                    },
-                    None => { return false; }
+
+    let wt_profile_guid_with_braces = format!("{{{}}}", wt_profile_guid);
+
+    let wt_profile_index = wt_profiles_list_array.iter()
+        .position(|profile| get_windows_terminal_profile_guid(profile) == Some(wt_profile_guid_with_braces.as_str()));
+
+    match wt_profile_index {
+        Some(index) => {
+            let wt_profile = &wt_profiles_list_array[index];
+            let mut profile_object = wt_profile.clone();
+            if let Some(profile_map) = profile_object.as_object_mut() {
+                for profile_template in profile_templates.iter() {
+                    profile_template.apply(profile_map);
                 }
-            },
-            None => { return false; }
-        }
-    };
+            }
 
-    let wt_profile = wt_profiles_list_array.iter_mut().find(wt_profiles_list_array_filter);
-    
-    match wt_profile {
-        Some(_profile) => { 
-            println!("Profile for `{}` already exists in the Windows Terminal settings.json file. Leaving it as is.", wsl_name);
+            if &profile_object == wt_profile {
+                message!("Profile for `{}` already exists in the Windows Terminal settings.json file and is up to date.", wsl_name);
+                return Ok(());
+            }
+
+            wt_config_document.replace_array_element(WT_PROFILES_LIST_PATH, index, &profile_object)
+                .with_context(|| format!("Could not update profile in Windows Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
+
+            write_jsonc_file(windows_terminal_config_path, &wt_config_document)
+                .with_context(|| format!("Could not write Windows Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
+
+            if !dry_run::is_enabled() {
+                message!("Profile for `{}` has been updated in the Windows Terminal settings.json file!", wsl_name);
+            }
         },
         None => {
-            let profile_object= serde_json::json!(
-            {
-                "guid": format!("{{{}}}", wt_profile_guid),
-                "hidden": false,
-                "name": wsl_name,
-                "commandline": format!("dragon run -w {}", wsl_name)
-            });
+            let profile_object = get_windows_terminal_profile_object(wt_profile_guid, wsl_name, profile_templates);
 
-            wt_profiles_list_array.insert(0, profile_object);
+            wt_config_document.prepend_array_element(WT_PROFILES_LIST_PATH, &profile_object)
+                .with_context(|| format!("Could not add profile to Windows Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
 
-            write_json_file(windows_terminal_config_path, &wt_config_content)
+            write_jsonc_file(windows_terminal_config_path, &wt_config_document)
                 .with_context(|| format!("Could not write Windows Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
 
-            println!("Profile for `{}` has been added to the Windows Terminal settings.json file! You can now connect to it in Windows Terminal by opening a new tab!", wsl_name);
+            if !dry_run::is_enabled() {
+                message!("Profile for `{}` has been added to the Windows Terminal settings.json file! You can now connect to it in Windows Terminal by opening a new tab!", wsl_name);
+            }
         }
     }
 
     Ok(())
 }
 
-fn pull_image_tag(image_url_str: &str) -> Result<()> {
-    let mut docker_pull_command = Command::new(r#"docker"#);
-    docker_pull_command.args(&["pull", image_url_str]);
+fn remove_windows_terminal_profile(windows_terminal_config_path: &PathBuf, wt_profile_guid: &str, wsl_name: &str) -> Result<()> {
+    let _file_lock = safe_file::lock(windows_terminal_config_path)?;
+    let mut wt_config_document = parse_jsonc_file(windows_terminal_config_path)
+        .with_context(|| format!("Could not parse Window Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
+
+    let wt_profiles_list_array = wt_config_document.array_elements(WT_PROFILES_LIST_PATH)
+        .with_context(|| format!("Windows Terminal settings file `{:#?}` doesn't have a `profiles.list` array property!", windows_terminal_config_path))?;
 
-    let docker_pull_command_status = docker_pull_command.status()
-        .with_context(|| format!("`docker pull {}` failed!", image_url_str))?;
-    if !docker_pull_command_status.success() {
-        return Err(anyhow::anyhow!("`docker pull {}` failed!", image_url_str));
-    }
+    let wt_profile_guid_with_braces = format!("{{{}}}", wt_profile_guid);
+
+    let wt_profile_index = wt_profiles_list_array.iter()
+        .position(|profile| get_windows_terminal_profile_guid(profile) == Some(wt_profile_guid_with_braces.as_str()));
+
+    let wt_profile_index = match wt_profile_index {
+        Some(index) => index,
+        None => {
+            message!("There is no profile for `{}` in the Windows Terminal settings.json file. Nothing to remove.", wsl_name);
+            return Ok(());
+        }
+    };
+
+    wt_config_document.remove_array_element(WT_PROFILES_LIST_PATH, wt_profile_index)
+        .with_context(|| format!("Could not remove profile from Windows Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
 
+    write_jsonc_file(windows_terminal_config_path, &wt_config_document)
+        .with_context(|| format!("Could not write Windows Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
+
+    if !dry_run::is_enabled() {
+        message!("Profile for `{}` has been removed from the Windows Terminal settings.json file!", wsl_name);
+    }
 
     Ok(())
 }
 
-fn docker_login(registry_name: &str, username: &str, password: &str) -> Result<()> {
-    let mut docker_login_command = Command::new(r#"docker"#);
-    docker_login_command.args(&["login", registry_name])
-                    .args(&["--username", username])
-                    .args(&["--password", password]);
+fn list_windows_terminal_profiles(windows_terminal_config_path: &PathBuf) -> Result<Vec<serde_json::Value>> {
+    let wt_config_document = parse_jsonc_file(windows_terminal_config_path)
+        .with_context(|| format!("Could not parse Window Terminal settings file `{:#?}`!", windows_terminal_config_path))?;
+
+    wt_config_document.array_elements(WT_PROFILES_LIST_PATH)
+        .with_context(|| format!("Windows Terminal settings file `{:#?}` doesn't have a `profiles.list` array property!", windows_terminal_config_path))
+}
+
+fn get_windows_terminal_profile_object(wt_profile_guid: &str, wsl_name: &str, profile_templates: &[&ProfileTemplate]) -> serde_json::Value {
+    let mut profile_object = serde_json::json!(
+    {
+        "guid": format!("{{{}}}", wt_profile_guid),
+        "hidden": false,
+        "name": wsl_name,
+        "commandline": format!("dragon run -w {}", wsl_name)
+    });
+
+    if let Some(profile_map) = profile_object.as_object_mut() {
+        for profile_template in profile_templates.iter() {
+            profile_template.apply(profile_map);
+        }
+    }
+
+    profile_object
+}
+
+/// The default profile template first, so the one of the WSL wins.
+fn get_profile_templates<'a>(default_profile_template: &'a Option<ProfileTemplate>, wsl_profile_template: &'a Option<ProfileTemplate>) -> Vec<&'a ProfileTemplate> {
+    default_profile_template.iter().chain(wsl_profile_template.iter()).collect()
+}
+
+/// `--no-terminal-profile` wins over `windows_terminal_profile_backend` in .dockerwsl.
+fn get_profile_backend(no_terminal_profile: bool, dockerwsl_content: &DockerWSLConf) -> ProfileBackend {
+    if no_terminal_profile {
+        return ProfileBackend::Disabled;
+    }
+
+    dockerwsl_content.windows_terminal_profile_backend.unwrap_or_default()
+}
+
+/// The settings.json file passed with `--wtconfig`, or if none is passed, the ones of the Windows Terminal installs found on this machine,
+/// limited to `windows_terminal_installs` from .dockerwsl if configured.
+fn get_windows_terminal_settings_paths(wtconfig_option: &Option<PathBuf>, selected_installs: &Option<Vec<WindowsTerminalInstall>>) -> Result<Vec<(WindowsTerminalInstall, PathBuf)>> {
+    if let Some(wtconfig) = wtconfig_option {
+        return Ok(vec![(WindowsTerminalInstall::from_settings_path(wtconfig), wtconfig.clone())]);
+    }
+
+    let selected_installs = selected_installs.as_deref().unwrap_or(&WindowsTerminalInstall::KNOWN[..]);
+    let wt_settings_paths = WindowsTerminalInstall::discover(selected_installs);
+
+    if wt_settings_paths.is_empty() {
+        return Err(anyhow::anyhow!("No Windows Terminal settings file found! Pass it with `--wtconfig` or `WT_SETTINGS_PATH`, or skip the profile with `--no-terminal-profile`."));
+    }
+
+    for (install, settings_path) in wt_settings_paths.iter() {
+        debug!("Found the settings file `{}` of Windows Terminal install {:?}.", settings_path.display(), install);
+    }
+
+    Ok(wt_settings_paths)
+}
+
+/// Replaces an earlier state for the same settings.json file.
+fn record_windows_terminal_profile_state(wsl_conf: &mut WSLConf, install: WindowsTerminalInstall, settings_path: &Path) {
+    let settings_path_string = settings_path.to_string_lossy().into_owned();
+
+    wsl_conf.windows_terminal_profiles.retain(|wt_profile_state| wt_profile_state.settings_path != settings_path_string);
+    wsl_conf.windows_terminal_profiles.push(WindowsTerminalProfileState { install, settings_path: settings_path_string });
+}
+
+/// The fragment file dragon owns, `%LOCALAPPDATA%\Microsoft\Windows Terminal\Fragments\dragon\profiles.json`.
+fn get_windows_terminal_fragment_path() -> Result<PathBuf> {
+    let local_app_data = std::env::var_os("LOCALAPPDATA")
+        .with_context(|| "The `LOCALAPPDATA` environment variable is not set, can't locate the Windows Terminal fragments folder!")?;
+
+    Ok(PathBuf::from(local_app_data).join("Microsoft").join("Windows Terminal").join("Fragments").join("dragon").join("profiles.json"))
+}
+
+fn update_windows_terminal_fragment(dockerwsl_path: &PathBuf) -> Result<()> {
+    let dockerwsl_content = get_dockerwsl_content(dockerwsl_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", dockerwsl_path))?;
+
+    write_windows_terminal_fragment(&dockerwsl_content)
+}
+
+/// Regenerates the fragment with one profile per WSL. Without any WSL left, the fragment is deleted.
+fn write_windows_terminal_fragment(dockerwsl_content: &DockerWSLConf) -> Result<()> {
+    let fragment_path = get_windows_terminal_fragment_path()?;
+    let wsls = &dockerwsl_content.wsls;
+
+    if wsls.is_empty() {
+        if fragment_path.exists() && dry_run::is_enabled() {
+            message!("Would delete Windows Terminal fragment `{}`, there are no WSLs left.", fragment_path.display());
+        } else if fragment_path.exists() {
+            std::fs::remove_file(&fragment_path)
+                .with_context(|| format!("Could not delete Windows Terminal fragment `{}`!", fragment_path.display()))?;
+            message!("Windows Terminal fragment `{}` has been deleted, there are no WSLs left!", fragment_path.display());
+        }
+
+        return Ok(());
+    }
+
+    let profiles: Vec<serde_json::Value> = wsls.iter()
+        .map(|wsl_conf| {
+            let profile_templates = get_profile_templates(&dockerwsl_content.default_windows_terminal_profile, &wsl_conf.windows_terminal_profile);
+            get_windows_terminal_profile_object(&wsl_conf.windows_terminal_profile_id, &wsl_conf.name, &profile_templates)
+        })
+        .collect();
+    let fragment_content = serde_json::json!({ "profiles": profiles });
 
-    let docker_login_command_status = docker_login_command.status()
-        .with_context(|| format!("`docker login {}` failed!", registry_name))?;
-    if !docker_login_command_status.success() {
-        return Err(anyhow::anyhow!("`docker login {}` failed. Double-check the service principal details in `.dockerwsl`!", registry_name));
+    if dry_run::is_enabled() {
+        dry_run::print_file_change(&fragment_path, &serde_json::to_string_pretty(&fragment_content)?);
+        return Ok(());
     }
 
+    let fragment_folder = fragment_path.parent()
+        .with_context(|| format!("Could not determine the folder of Windows Terminal fragment `{}`!", fragment_path.display()))?;
+    create_dir_all(fragment_folder)
+        .with_context(|| format!("Could not create the Windows Terminal fragments folder `{}`!", fragment_folder.display()))?;
+
+    // the fragment is regenerated from .dockerwsl every time, there is nothing to back up
+    safe_file::write(&fragment_path, serde_json::to_string_pretty(&fragment_content)?.as_bytes())
+        .with_context(|| format!("An error happened while writing Windows Terminal fragment `{}`", fragment_path.display()))?;
+
+    message!("Windows Terminal fragment `{}` has been updated with the profiles of {} WSL(s)! Restart Windows Terminal to see the changes.", fragment_path.display(), wsls.len());
 
     Ok(())
 }
 
+fn list_windows_terminal_fragment_profiles() -> Result<Vec<serde_json::Value>> {
+    let fragment_path = get_windows_terminal_fragment_path()?;
+
+    if !fragment_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let fragment_document = parse_jsonc_file(&fragment_path)
+        .with_context(|| format!("Could not parse Windows Terminal fragment `{}`!", fragment_path.display()))?;
+
+    fragment_document.array_elements(&["profiles"])
+        .with_context(|| format!("Windows Terminal fragment `{}` doesn't have a `profiles` array property!", fragment_path.display()))
+}
+
+fn get_windows_terminal_profile_guid(profile: &serde_json::Value) -> Option<&str> {
+    profile.get("guid").and_then(|guid| guid.as_str())
+}
+
+fn is_dragon_windows_terminal_profile(profile: &serde_json::Value) -> bool {
+    profile.get("commandline")
+        .and_then(|commandline| commandline.as_str())
+        .is_some_and(|commandline| commandline.starts_with("dragon run"))
+}
 
-fn parse_json_file_without_comments(file_path: &PathBuf) -> Result<serde_json::Value> {
+fn parse_jsonc_file(file_path: &PathBuf) -> Result<JsoncDocument> {
     let file_path_str = file_path.to_str().unwrap();
-    debug!("Attempting to parse json file `{}` (comments will be removed).", file_path_str);
+    debug!("Attempting to parse json file `{}` (comments will be kept).", file_path_str);
 
     let file_content_str = std::fs::read_to_string(file_path)
         .with_context(|| format!("Could not read json file `{}`", file_path_str))?;
 
-    let file_reader = StripComments::new(file_content_str.as_bytes());
-    let file_content: serde_json::Value = serde_json::from_reader(file_reader).with_context(|| "Could not parse json file!")?;
+    let file_content = JsoncDocument::parse(file_content_str).with_context(|| "Could not parse json file!")?;
 
     debug!("File `{}` was parsed successfully!", file_path_str);
     Ok(file_content)
 }
 
-fn write_json_file(file_path: &PathBuf, json_content: &serde_json::Value) -> Result<()> {
+fn write_jsonc_file(file_path: &Path, json_document: &JsoncDocument) -> Result<()> {
     let file_path_str = file_path.to_str().unwrap();
     debug!("Attempting to write to json file `{}`.", file_path_str);
 
-    debug!("{:#?}",json_content); 
+    if dry_run::is_enabled() {
+        dry_run::print_file_change(file_path, json_document.text());
+        return Ok(());
+    }
 
-    let file =  OpenOptions::new().write(true).truncate(true).create(true).open(file_path)
-        .with_context(|| format!("Could not open json file `{}` for writing!", file_path_str))?;
-    let file_writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(file_writer, json_content).with_context(|| format!("An error happened while writing to json file `{}`", file_path_str))?;
+    safe_file::write_with_backup(file_path, json_document.text().as_bytes())
+        .with_context(|| format!("An error happened while writing to json file `{}`", file_path_str))?;
 
     debug!("File `{}` was updated successfully!", file_path_str);
     Ok(())
@@ -843,8 +2793,14 @@ fn get_dockerwsl_content(file_path: &PathBuf) -> Result<DockerWSLConf> {
         return parse_dockerwslconf_file(file_path);
     } else {
         return Ok(DockerWSLConf {
+            version: schema::CURRENT_VERSION,
             wsls: vec![],
             default_base_wsl_install_path: None,
+            default_keep_versions: None,
+            runtime: None,
+            windows_terminal_profile_backend: None,
+            windows_terminal_installs: None,
+            default_windows_terminal_profile: None,
             private_registries: vec![]
         });
     }
@@ -855,73 +2811,142 @@ fn parse_dockerwslconf_file(file_path: &PathBuf) -> Result<DockerWSLConf> {
 
     debug!("Attempting to parse `.dockerwsl` conf file `{}`.", file_path_str);
 
-    let file = File::open(file_path)
+    let file_text = std::fs::read_to_string(file_path)
         .with_context(|| format!("Could not open .dockerwsl file `{}` for reading!", file_path_str))?;
-    let file_reader = BufReader::new(file);
-    let file_content: DockerWSLConf = serde_yaml::from_reader(file_reader).with_context(|| "Could not parse yaml file!")?;
+    let file_content = parse_dockerwsl_text(&file_text)?;
 
     debug!("`.dockerwsl` file `{}` was parsed successfully!", file_path_str);
     Ok(file_content)
 }
 
+/// Parses .dockerwsl, migrating it to the current schema version in memory. It is written with the current version next time.
+fn parse_dockerwsl_text(dockerwsl_text: &str) -> Result<DockerWSLConf> {
+    let (dockerwsl_document, version) = schema::parse(dockerwsl_text)?;
+    if version < schema::CURRENT_VERSION {
+        debug!("`.dockerwsl` has schema version {}, it has been migrated to version {}.", version, schema::CURRENT_VERSION);
+    }
+
+    // values of the wrong type are only reported with their key and line when parsed from text, not from the document
+    let migrated_dockerwsl_text = if version == schema::CURRENT_VERSION {
+        dockerwsl_text.to_string()
+    } else {
+        serde_yaml::to_string(&dockerwsl_document).with_context(|| "Could not serialize the migrated .dockerwsl file!")?
+    };
+
+    serde_yaml::from_str(&migrated_dockerwsl_text).with_context(|| "Could not parse yaml file!")
+}
+
+/// Reads .dockerwsl, lets `change` change it and writes it back, all while holding the lock of .dockerwsl.
+/// Commands that take long should read .dockerwsl again this way when they are done, instead of writing what they read at the start,
+/// so the changes other dragon processes made in the meantime are kept.
+fn update_dockerwsl_file<T>(file_path: &PathBuf, change: impl FnOnce(&mut DockerWSLConf) -> Result<T>) -> Result<T> {
+    let _file_lock = safe_file::lock(file_path)?;
+
+    let mut dockerwsl_content = get_dockerwsl_content(file_path)
+        .with_context(|| format!("Could not parse `.dockerwsl` config file `{:#?}`!", file_path))?;
+    let changed = change(&mut dockerwsl_content)?;
+
+    write_dockerwsl_file(file_path, &dockerwsl_content)?;
+
+    Ok(changed)
+}
+
+/// Puts each of `wsl_confs` in place of the entry with the same name, entries removed in the meantime stay removed.
+fn replace_wsl_confs(dockerwsl_content: &mut DockerWSLConf, wsl_confs: &[&WSLConf]) {
+    for wsl_conf in wsl_confs.iter() {
+        match dockerwsl_content.wsls.iter_mut().find(|current_wsl_conf| current_wsl_conf.name == wsl_conf.name) {
+            Some(current_wsl_conf) => *current_wsl_conf = (*wsl_conf).clone(),
+            None => message!("WSL `{}` has been removed from .dockerwsl in the meantime, its changes are not kept!", &wsl_conf.name)
+        }
+    }
+}
+
+/// Only call this while holding the lock of .dockerwsl, see `update_dockerwsl_file`.
 fn write_dockerwsl_file(file_path: &PathBuf, dockerwsl_conf: &DockerWSLConf) -> Result<()> {
     let file_path_str = file_path.to_str().unwrap();
     debug!("Attempting to update .dockerwsl file `{}`.", file_path_str);
 
     debug!("{:#?}",dockerwsl_conf); 
 
-    let file =  OpenOptions::new().write(true).truncate(true).create(true).open(file_path)
-        .with_context(|| format!("Could not open .dockerwsl file `{}` for writing!", file_path_str))?;
-    let file_writer = BufWriter::new(file);
-    serde_yaml::to_writer(file_writer, dockerwsl_conf).with_context(|| format!("An error happened while writing .dockerwsl file to `{}`", file_path_str))?;
+    let dockerwsl_yaml = serde_yaml::to_string(dockerwsl_conf)
+        .with_context(|| "Could not serialize the .dockerwsl file!")?;
+
+    write_dockerwsl_text(file_path, &dockerwsl_yaml)
+}
+
+/// Writes .dockerwsl as it is given, like `dragon config edit` does to keep comments. Only call this while holding the lock of .dockerwsl.
+fn write_dockerwsl_text(file_path: &Path, dockerwsl_yaml: &str) -> Result<()> {
+    let file_path_str = file_path.to_str().unwrap();
+
+    if dry_run::is_enabled() {
+        dry_run::print_file_change(file_path, dockerwsl_yaml);
+        return Ok(());
+    }
+
+    safe_file::write_with_backup(file_path, dockerwsl_yaml.as_bytes())
+        .with_context(|| format!("An error happened while writing .dockerwsl file to `{}`", file_path_str))?;
 
     debug!("File `{}` was updated successfully!", file_path_str);
     Ok(())
 }
 
-fn az_login(username: &str, password: &str, tenant: &str, az_cli_path: &PathBuf) -> Result<()> {
-    let az_cli_path_str = az_cli_path.to_str().unwrap();
 
-    let mut az_login_command = Command::new(az_cli_path_str);
-    az_login_command.args(&["login", "--service-principal"])
-                    .args(&["--username", username])
-                    .args(&["--password", password])
-                    .args(&["--tenant", tenant]);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let az_login_command_status = az_login_command.status()
-        .with_context(|| format!("`az login --service-principal` failed!"))?;
-    if !az_login_command_status.success() {
-        return Err(anyhow::anyhow!("`az login --service-principal` failed. Double-check the service principal details in `.dockerwsl`!"));
+    fn wsl_conf(name: &str, image: &str, history_tags: &[&str]) -> WSLConf {
+        let mut wsl_conf: WSLConf = serde_yaml::from_str(&format!("name: {}\nimage: {}\nwindows_terminal_profile_id: c6eaf9f4-32a7-4fdc-b5cf-066e8a4b1e40\nbase_install_path: /wsl/{}\n", name, image, name)).unwrap();
+        for tag in history_tags.iter() {
+            record_installed_version(&mut wsl_conf, InstalledVersion::new(&format!("alexchiri/{}:{}", name, tag), tag, None, Path::new("/wsl")));
+        }
+
+        wsl_conf
     }
-    
-    Ok(())
-}
 
-fn get_latest_tag(registry_name:&str, repository_name: &str, username: &str, password: &str, tenant: &str, az_cli_path: &PathBuf) -> Result<String> {
-    az_login(username, password, tenant, az_cli_path).with_context(|| format!("There was an error while logging in to Azure!"))?;
-    
-    let az_cli_path_str = az_cli_path.to_str().unwrap();
-
-    let mut az_get_latest_tag_command = Command::new(az_cli_path_str);
-    az_get_latest_tag_command.args(&["acr", "repository", "show-manifests"])
-                             .args(&["-n", registry_name])
-                             .args(&["--repository", repository_name])
-                             .args(&["--orderby", "time_desc"])
-                             .args(&["--top", "1"])
-                             .args(&["--query", "[0].tags[0]"]);
-    let az_get_latest_tag_command_output = az_get_latest_tag_command.output()
-        .with_context(|| format!("Failed to retrieve the latest tag for {}/{}!", registry_name, repository_name))?;
-    
-    let az_latest_tag_output = String::from_utf8(az_get_latest_tag_command_output.stdout)
-        .with_context(|| format!("Could not convert latest tag to UTF-8 string!"))?;
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
 
-    let image_tag_regex = regex::Regex::new(r#""(.+?)"\r\n"#).unwrap();
-    let image_tag_captures = image_tag_regex.captures(az_latest_tag_output.as_str())
-            .with_context(|| format!("Docker image tag does not have the expected format!"))?;
-    let latest_tag = image_tag_captures.get(1)
-        .with_context(|| format!("Could not extract latest tag from az CLI output `{}`!", az_latest_tag_output.as_str()))?
-        .as_str();
+    #[test]
+    fn finds_only_the_wsl_vms_dragon_installed() {
+        let ubuntu = wsl_conf("ubuntu", "alexchiri/ubuntu:22.04-3", &["22.04-2"]);
+        let wsl_vms = names(&["Ubuntu", "ubuntu-22.04", "ubuntu-22.04-1", "ubuntu-22.04-2", "ubuntu-22.04-3", "docker-desktop"]);
 
-    return Ok(String::from(latest_tag));
-}
+        // `ubuntu-22.04` is a distro of the user's own, `ubuntu-22.04-1` was installed by dragon but is not in the history anymore
+        assert_eq!(get_wsl_vms_for_wsl(&ubuntu, &["ubuntu"], &wsl_vms, &names(&["ubuntu-22.04-1", "ubuntu-22.04-3"])),
+            names(&["ubuntu-22.04-1", "ubuntu-22.04-2", "ubuntu-22.04-3"]));
+        assert_eq!(get_wsl_vms_for_wsl(&ubuntu, &["ubuntu"], &wsl_vms, &[]), names(&["ubuntu-22.04-2", "ubuntu-22.04-3"]));
+    }
+
+    #[test]
+    fn warns_about_registries_without_credentials() {
+        let dockerwsl_content: DockerWSLConf = serde_yaml::from_str("version: 1\nwsls: []\nprivate_registries:\n  - name: dragon.azurecr.io\n    username: dragon\n    password: hunter2\n").unwrap();
+        let dockerwsl_content = DockerWSLConf {
+            wsls: vec![
+                wsl_conf("dev", "dragon.azurecr.io/dev:1.0", &[]),
+                wsl_conf("web", "ghcr.io/alexchiri/web:1.0", &[]),
+                wsl_conf("ubuntu", "docker.io/library/ubuntu:22.04", &[])
+            ],
+            ..dockerwsl_content
+        };
 
+        let registry_warnings: Vec<String> = check_dockerwsl_content(&dockerwsl_content).warnings.into_iter()
+            .filter(|warning| warning.contains("private_registries"))
+            .collect();
+        assert_eq!(registry_warnings, vec!["WSL `web`: registry `ghcr.io` has no entry in `private_registries`, pulling `ghcr.io/alexchiri/web:1.0` only works if the registry is public!"]);
+    }
+
+    #[test]
+    fn leaves_the_wsl_vms_of_wsls_with_longer_names() {
+        let dev = wsl_conf("dev", "alexchiri/dev:1.1", &["1.0"]);
+        let dev_tools = wsl_conf("dev-tools", "alexchiri/dev-tools:1.0", &[]);
+        let wsl_vms = names(&["dev-1.0", "dev-1.1", "dev-tools-1.0"]);
+        let install_folders = names(&["dev-1.0", "dev-1.1", "dev-tools-1.0"]);
+
+        assert_eq!(get_wsl_vms_for_wsl(&dev, &["dev", "dev-tools"], &wsl_vms, &install_folders), names(&["dev-1.0", "dev-1.1"]));
+        assert_eq!(get_wsl_vms_for_wsl(&dev_tools, &["dev", "dev-tools"], &wsl_vms, &install_folders), names(&["dev-tools-1.0"]));
+        // without `dev-tools` configured, its WSL VM could be a `dev` one with tag `tools-1.0`, but dragon never installed that tag
+        assert_eq!(get_wsl_vms_for_wsl(&dev, &["dev"], &wsl_vms, &names(&["dev-1.0"])), names(&["dev-1.0", "dev-1.1"]));
+    }
+}
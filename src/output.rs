@@ -0,0 +1,356 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// Prints a progress message for people. With `--output json` it goes to stderr, so stdout only has the JSON report.
+macro_rules! message {
+    ($($arg:tt)*) => {
+        crate::output::print_message(&format!($($arg)*))
+    };
+}
+
+/// How results are printed, set with `--output`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Progress messages for people.
+    #[default]
+    Text,
+    /// A single JSON report on stdout once the command is done.
+    Json
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(output_format: &str) -> Result<Self> {
+        match output_format.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow::anyhow!("Unknown output format `{}`, valid options are text and json!", output_format))
+        }
+    }
+}
+
+/// What a command did to one WSL. Only the fields that apply to the command are set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WslResult {
+    pub wsl: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_tag: Option<String>,
+    /// The `latest` tag in .dockerwsl, reported by `status`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_guid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// WSL VMs unregistered by `remove` and `cleanup`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_vms: Vec<String>,
+    /// WSL VMs installed for the WSL, reported by `status`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vms: Vec<VmStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub drifts: Vec<String>,
+    pub error: Option<String>
+}
+
+impl WslResult {
+    pub fn new(wsl_name: &str) -> WslResult {
+        WslResult { wsl: wsl_name.to_string(), ..WslResult::default() }
+    }
+
+    pub fn failed(wsl_name: &str, error: &anyhow::Error) -> WslResult {
+        WslResult { wsl: wsl_name.to_string(), error: Some(format!("{:#}", error)), ..WslResult::default() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VmStatus {
+    pub name: String,
+    pub state: String,
+    pub version: String,
+    pub in_use: bool,
+    pub latest: bool
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    command: &'a str,
+    dry_run: bool,
+    results: Vec<WslResult>,
+    /// Drift that doesn't belong to a single WSL, like WSL VMs or profiles without a .dockerwsl entry.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    drifts: Vec<String>,
+    /// Why the command as a whole failed.
+    error: Option<String>
+}
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+static RESULTS: Mutex<Vec<WslResult>> = Mutex::new(Vec::new());
+static DRIFTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Put in front of the messages of the thread, so the messages of WSLs handled at the same time with `--jobs` can be told apart.
+    static MESSAGE_PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_format(output_format: OutputFormat) {
+    JSON_OUTPUT.store(output_format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+pub fn set_message_prefix(prefix: Option<&str>) {
+    MESSAGE_PREFIX.with(|message_prefix| *message_prefix.borrow_mut() = prefix.map(|prefix| prefix.to_string()));
+}
+
+pub fn has_message_prefix() -> bool {
+    MESSAGE_PREFIX.with(|message_prefix| message_prefix.borrow().is_some())
+}
+
+pub fn print_message(message: &str) {
+    let message = MESSAGE_PREFIX.with(|message_prefix| match message_prefix.borrow().as_ref() {
+        Some(prefix) => prefix_lines(prefix, message),
+        None => message.to_string()
+    });
+
+    if is_json() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+fn prefix_lines(prefix: &str, message: &str) -> String {
+    message.split('\n')
+        .map(|line| format!("[{}] {}", prefix, line))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// A `wsl` or container runtime process started with `spawn_child`.
+pub struct ChildProcess {
+    pub child: Child,
+    forwarders: Vec<JoinHandle<()>>
+}
+
+impl ChildProcess {
+    /// Waits for the process to exit and for the rest of its output to be printed.
+    pub fn wait(mut self) -> io::Result<ExitStatus> {
+        let exit_status = self.child.wait()?;
+        for forwarder in self.forwarders.drain(..) {
+            let _ = forwarder.join();
+        }
+
+        Ok(exit_status)
+    }
+}
+
+/// Starts a `wsl` or container runtime command. Its output goes to stdout, or stderr with `--output json` to keep stdout
+/// parseable. With a message prefix (`--jobs`) stdout and stderr are forwarded line by line as messages instead, so the
+/// output of WSLs handled at the same time doesn't get mixed up.
+pub fn spawn_child(command: &mut Command) -> io::Result<ChildProcess> {
+    let message_prefix = MESSAGE_PREFIX.with(|message_prefix| message_prefix.borrow().clone());
+    if message_prefix.is_none() {
+        command.stdout(if is_json() { Stdio::from(io::stderr()) } else { Stdio::inherit() });
+        return Ok(ChildProcess { child: command.spawn()?, forwarders: vec![] });
+    }
+
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+
+    let mut forwarders = vec![];
+    if let Some(stdout) = child.stdout.take() {
+        forwarders.push(forward_lines(stdout, message_prefix.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forwarders.push(forward_lines(stderr, message_prefix));
+    }
+
+    Ok(ChildProcess { child, forwarders })
+}
+
+fn forward_lines<R: Read + Send + 'static>(child_output: R, message_prefix: Option<String>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        set_message_prefix(message_prefix.as_deref());
+
+        let mut child_output = BufReader::new(child_output);
+        let mut line = vec![];
+        while matches!(child_output.read_until(b'\n', &mut line), Ok(read) if read > 0) {
+            let line_string = child_output_line(&line);
+            if !line_string.is_empty() {
+                print_message(&line_string);
+            }
+            line.clear();
+        }
+    })
+}
+
+/// `wsl` writes UTF-16, without the zero bytes its messages read as plain text like the ones of the container runtime.
+fn child_output_line(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).replace(char::from(0), "").trim_end().to_string()
+}
+
+pub fn record(wsl_result: WslResult) {
+    RESULTS.lock().unwrap().push(wsl_result);
+}
+
+pub fn record_drift(drift: String) {
+    DRIFTS.lock().unwrap().push(drift);
+}
+
+/// Names of the WSLs recorded with an error so far.
+pub fn failed_wsls() -> Vec<String> {
+    RESULTS.lock().unwrap().iter()
+        .filter(|wsl_result| wsl_result.error.is_some())
+        .map(|wsl_result| wsl_result.wsl.clone())
+        .collect()
+}
+
+/// Prints a table with the outcome of every WSL recorded so far, for commands going through all WSLs.
+/// Nothing is printed for JSON output, the report has the same results.
+pub fn print_summary() {
+    if is_json() {
+        return;
+    }
+
+    let results = RESULTS.lock().unwrap();
+    if results.is_empty() {
+        return;
+    }
+
+    println!();
+    for line in summary_lines(&results) {
+        println!("{}", line);
+    }
+}
+
+fn summary_lines(results: &[WslResult]) -> Vec<String> {
+    let rows: Vec<(&str, &str, String)> = results.iter()
+        .map(|wsl_result| match &wsl_result.error {
+            Some(error) => (wsl_result.wsl.as_str(), "FAILED", error.clone()),
+            None => (wsl_result.wsl.as_str(), "ok", summary_details(wsl_result))
+        })
+        .collect();
+
+    let wsl_width = rows.iter().map(|(wsl, _outcome, _details)| wsl.len()).max().unwrap_or(0).max("WSL".len());
+    let outcome_width = "FAILED".len();
+
+    let mut lines = vec![format!("{:<wsl_width$}  {:<outcome_width$}  DETAILS", "WSL", "RESULT", wsl_width = wsl_width, outcome_width = outcome_width)];
+    for (wsl, outcome, details) in rows.iter() {
+        lines.push(format!("{:<wsl_width$}  {:<outcome_width$}  {}", wsl, outcome, details, wsl_width = wsl_width, outcome_width = outcome_width)
+            .trim_end().to_string());
+    }
+
+    lines
+}
+
+/// What changed for a WSL that went fine: the tag change, otherwise the WSL VMs removed or the image digest.
+fn summary_details(wsl_result: &WslResult) -> String {
+    if let Some(new_tag) = &wsl_result.new_tag {
+        match &wsl_result.old_tag {
+            Some(old_tag) if old_tag != new_tag => format!("{} -> {}", old_tag, new_tag),
+            _ => new_tag.clone()
+        }
+    } else if !wsl_result.removed_vms.is_empty() {
+        format!("removed {}", wsl_result.removed_vms.join(", "))
+    } else {
+        wsl_result.digest.clone().unwrap_or_default()
+    }
+}
+
+/// Prints the JSON report with everything recorded while the command ran, nothing is printed for text output.
+pub fn print_report(command: &str, dry_run: bool, command_result: &Result<()>) {
+    if !is_json() {
+        return;
+    }
+
+    let report = Report {
+        command,
+        dry_run,
+        results: RESULTS.lock().unwrap().clone(),
+        drifts: DRIFTS.lock().unwrap().clone(),
+        error: command_result.as_ref().err().map(|error| format!("{:#}", error))
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_only_the_fields_that_apply() {
+        let mut upgraded = WslResult::new("dev");
+        upgraded.old_tag = Some("1".to_string());
+        upgraded.new_tag = Some("2".to_string());
+        upgraded.vm_name = Some("dev-2".to_string());
+
+        let mut failed = WslResult::new("web");
+        failed.error = Some("Could not pull the image!".to_string());
+
+        let report = Report { command: "upgrade", dry_run: false, results: vec![upgraded, failed], drifts: vec![], error: None };
+
+        assert_eq!(serde_json::to_value(&report).unwrap(), serde_json::json!({
+            "command": "upgrade",
+            "dry_run": false,
+            "results": [
+                { "wsl": "dev", "old_tag": "1", "new_tag": "2", "vm_name": "dev-2", "error": null },
+                { "wsl": "web", "error": "Could not pull the image!" }
+            ],
+            "error": null
+        }));
+        assert_eq!("JSON".parse::<OutputFormat>().unwrap(), OutputFormat::Json);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn prefixes_every_line_of_a_message() {
+        assert_eq!(prefix_lines("dev", "Would change `.dockerwsl`:\n    - latest: 1"), "[dev] Would change `.dockerwsl`:\n[dev]     - latest: 1");
+    }
+
+    #[test]
+    fn reads_child_output_lines_in_utf8_and_utf16() {
+        assert_eq!(child_output_line(b"latest: Pulling from library/ubuntu\n"), "latest: Pulling from library/ubuntu");
+        assert_eq!(child_output_line(b"O\0p\0e\0r\0a\0t\0i\0o\0n\0 \0d\0o\0n\0e\0.\0\r\0\n"), "Operation done.");
+    }
+
+    #[test]
+    fn summarizes_results_as_a_table() {
+        let mut updated = WslResult::new("dev");
+        updated.old_tag = Some("1".to_string());
+        updated.new_tag = Some("2".to_string());
+
+        let mut unchanged = WslResult::new("data-science");
+        unchanged.old_tag = Some("7".to_string());
+        unchanged.new_tag = Some("7".to_string());
+
+        let failed = WslResult::failed("web", &anyhow::anyhow!("Could not pull the image!"));
+
+        assert_eq!(summary_lines(&[updated, unchanged, failed]), vec![
+            "WSL           RESULT  DETAILS",
+            "dev           ok      1 -> 2",
+            "data-science  ok      7",
+            "web           FAILED  Could not pull the image!"
+        ]);
+    }
+}
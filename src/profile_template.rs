@@ -0,0 +1,98 @@
+use serde::{Serialize, Deserialize};
+
+/// Windows Terminal profile settings applied to the profile of a WSL, on top of the `guid`, `name` and `commandline` dragon writes.
+///
+/// Configured per WSL with `windows_terminal_profile` and for all WSLs with `default_windows_terminal_profile` in .dockerwsl,
+/// the per WSL values win. In .dockerwsl it looks like
+/// `windows_terminal_profile: { icon: 'C:\icons\dev.png', color_scheme: Campbell, font: { face: Cascadia Code, size: 11 } }`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileTemplate {
+    pub icon: Option<String>,
+    pub color_scheme: Option<String>,
+    pub font: Option<ProfileFont>,
+    pub starting_directory: Option<String>,
+    pub tab_title: Option<String>,
+    pub background_image: Option<String>,
+    /// Any other Windows Terminal profile settings, written as they are, e.g. `{ opacity: 90, useAcrylic: true }`.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra: serde_json::Map<String, serde_json::Value>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileFont {
+    pub face: Option<String>,
+    pub size: Option<f64>,
+    pub weight: Option<String>
+}
+
+impl ProfileTemplate {
+    /// Sets the configured settings on the Windows Terminal profile, settings that are not configured are left untouched.
+    pub fn apply(&self, profile: &mut serde_json::Map<String, serde_json::Value>) {
+        let settings = [
+            ("icon", &self.icon),
+            ("colorScheme", &self.color_scheme),
+            ("startingDirectory", &self.starting_directory),
+            ("tabTitle", &self.tab_title),
+            ("backgroundImage", &self.background_image)
+        ];
+
+        for (key, value) in settings.iter() {
+            if let Some(value) = value {
+                profile.insert(key.to_string(), serde_json::Value::from(value.as_str()));
+            }
+        }
+
+        if let Some(font) = &self.font {
+            let profile_font = profile.entry("font").or_insert_with(|| serde_json::json!({}));
+            if !profile_font.is_object() {
+                *profile_font = serde_json::json!({});
+            }
+
+            let profile_font = profile_font.as_object_mut().unwrap();
+            if let Some(face) = &font.face {
+                profile_font.insert("face".to_string(), serde_json::Value::from(face.as_str()));
+            }
+            if let Some(size) = font.size {
+                profile_font.insert("size".to_string(), serde_json::Value::from(size));
+            }
+            if let Some(weight) = &font.weight {
+                profile_font.insert("weight".to_string(), serde_json::Value::from(weight.as_str()));
+            }
+        }
+
+        for (key, value) in self.extra.iter() {
+            profile.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_default_and_wsl_templates_in_order() {
+        let default_template: ProfileTemplate = serde_yaml::from_str(
+            "color_scheme: Campbell\nfont: { face: Cascadia Code, size: 11 }\nextra: { opacity: 90 }"
+        ).unwrap();
+        let wsl_template: ProfileTemplate = serde_yaml::from_str(
+            "icon: 'C:\\icons\\dev.png'\nfont: { size: 13 }\nextra: { opacity: 100, useAcrylic: true }"
+        ).unwrap();
+
+        let mut profile = serde_json::json!({ "guid": "{1}", "name": "dev", "font": { "weight": "bold" }, "hidden": true });
+        let profile_object = profile.as_object_mut().unwrap();
+        default_template.apply(profile_object);
+        wsl_template.apply(profile_object);
+
+        assert_eq!(profile, serde_json::json!({
+            "guid": "{1}",
+            "name": "dev",
+            "font": { "weight": "bold", "face": "Cascadia Code", "size": 13.0 },
+            "hidden": true,
+            "colorScheme": "Campbell",
+            "opacity": 100,
+            "icon": "C:\\icons\\dev.png",
+            "useAcrylic": true
+        }));
+    }
+}
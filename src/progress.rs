@@ -0,0 +1,217 @@
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::output;
+
+/// How often a progress line on a terminal is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+/// How often progress is logged when it can't be redrawn in place.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+/// How often a running child process is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Progress of a long step: downloading a layer, writing a tar, exporting or importing a WSL VM.
+///
+/// On a terminal it is a line redrawn in place with a spinner. Otherwise, with `--output json` or with `--jobs` where
+/// several steps run at the same time, it falls back to a message every few seconds. Either way the step ends with a
+/// message saying how long it took.
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    position: u64,
+    started: Instant,
+    last_shown: Instant,
+    frame: usize,
+    live: bool,
+    finished: bool
+}
+
+impl Progress {
+    /// Starts the progress of a step, `total` is the number of bytes it goes through if known up front.
+    pub fn start(label: &str, total: Option<u64>) -> Progress {
+        let now = Instant::now();
+        let mut progress = Progress {
+            label: label.to_string(),
+            total,
+            position: 0,
+            started: now,
+            last_shown: now,
+            frame: 0,
+            live: io::stdout().is_terminal() && !output::is_json() && !output::has_message_prefix(),
+            finished: false
+        };
+
+        if progress.live {
+            progress.redraw();
+        } else {
+            message!("{}...", label);
+        }
+
+        progress
+    }
+
+    pub fn advance(&mut self, bytes: u64) {
+        self.set_position(self.position + bytes);
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+        self.tick();
+    }
+
+    /// Shows that the step is still going, for steps without bytes to count it only updates the elapsed time.
+    pub fn tick(&mut self) {
+        let since_last_shown = self.last_shown.elapsed();
+
+        if self.live && since_last_shown >= REDRAW_INTERVAL {
+            self.redraw();
+        } else if !self.live && since_last_shown >= LOG_INTERVAL {
+            message!("{}", status_line(&self.label, self.position, self.total, self.started.elapsed()));
+            self.last_shown = Instant::now();
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.clear_line();
+        self.finished = true;
+
+        let elapsed = format_elapsed(self.started.elapsed());
+        if self.position > 0 {
+            message!("{} done, {} in {}.", &self.label, format_bytes(self.position), elapsed);
+        } else {
+            message!("{} done in {}.", &self.label, elapsed);
+        }
+    }
+
+    fn redraw(&mut self) {
+        let spinner = SPINNER_FRAMES[self.frame % SPINNER_FRAMES.len()];
+        print!("\r\x1b[2K{} {}", spinner, status_line(&self.label, self.position, self.total, self.started.elapsed()));
+        let _ = io::stdout().flush();
+
+        self.frame += 1;
+        self.last_shown = Instant::now();
+    }
+
+    fn clear_line(&self) {
+        if self.live {
+            print!("\r\x1b[2K");
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+impl Drop for Progress {
+    /// A step that failed leaves no half drawn progress line behind for the error message.
+    fn drop(&mut self) {
+        if !self.finished {
+            self.clear_line();
+        }
+    }
+}
+
+/// Counts the bytes written through it as progress.
+pub struct ProgressWriter<'a, W: Write> {
+    inner: W,
+    progress: &'a mut Progress
+}
+
+impl<'a, W: Write> ProgressWriter<'a, W> {
+    pub fn new(inner: W, progress: &'a mut Progress) -> ProgressWriter<'a, W> {
+        ProgressWriter { inner, progress }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.advance(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Waits for a child process to exit. The progress follows the size of `output_path`, the file the process writes,
+/// or only the elapsed time without one.
+pub fn wait_for_child(mut child_process: output::ChildProcess, progress: &mut Progress, output_path: Option<&Path>) -> io::Result<ExitStatus> {
+    loop {
+        if child_process.child.try_wait()?.is_some() {
+            return child_process.wait();
+        }
+
+        match output_path.and_then(|output_path| fs::metadata(output_path).ok()) {
+            Some(output_metadata) => progress.set_position(output_metadata.len()),
+            None => progress.tick()
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn status_line(label: &str, position: u64, total: Option<u64>, elapsed: Duration) -> String {
+    let elapsed = format_elapsed(elapsed);
+
+    match total {
+        Some(total) if total > 0 => format!("{}: {} of {} ({}%), {}", label, format_bytes(position), format_bytes(total),
+            (position.min(total) * 100) / total, elapsed),
+        _ if position > 0 => format!("{}: {}, {}", label, format_bytes(position), elapsed),
+        _ => format!("{}: {}", label, elapsed)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit_index = 0;
+    while value >= 1024.0 && unit_index < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit_index += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit_index])
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_progress() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(format_elapsed(Duration::from_secs(42)), "42s");
+        assert_eq!(format_elapsed(Duration::from_secs(125)), "2m 05s");
+
+        assert_eq!(status_line("Downloading layer 1/2", 512 * 1024, Some(2 * 1024 * 1024), Duration::from_secs(3)),
+            "Downloading layer 1/2: 512.0 KiB of 2.0 MiB (25%), 3s");
+        assert_eq!(status_line("Writing `rootfs.tar`", 2048, None, Duration::from_secs(61)), "Writing `rootfs.tar`: 2.0 KiB, 1m 01s");
+        assert_eq!(status_line("Importing WSL VM `dev-1`", 0, None, Duration::from_secs(7)), "Importing WSL VM `dev-1`: 7s");
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+/// Registry host names that all mean Docker Hub.
+const DOCKER_HUB_DOMAINS: [&str; 3] = ["docker.io", "index.docker.io", "registry-1.docker.io"];
+
+const DIGEST_PATTERN: &str = r"^[A-Za-z][A-Za-z0-9]*(?:[-_+.][A-Za-z][A-Za-z0-9]*)*:[0-9a-fA-F]{32,}$";
+const TAG_PATTERN: &str = r"^[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}$";
+const DOMAIN_PATTERN: &str = r"^(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))*(?::[0-9]+)?$";
+const PATH_COMPONENT_PATTERN: &str = r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*$";
+
+/// A Docker image reference like `localhost:5000/team/sub/img:1.0@sha256:...`, parsed following the grammar of
+/// https://github.com/distribution/reference: `[domain[:port]/]path-component[/path-component...][:tag][@digest]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageReference {
+    /// Registry host with an optional port, `None` for references without one, which point to Docker Hub.
+    pub domain: Option<String>,
+    /// Repository path as it was written, e.g. `team/sub/img` or `ubuntu`.
+    pub path: String,
+    pub tag: Option<String>,
+    /// Manifest digest like `sha256:<hex>`.
+    pub digest: Option<String>
+}
+
+impl ImageReference {
+    pub fn parse(reference: &str) -> Result<ImageReference> {
+        ImageReference::parse_parts(reference)
+            .with_context(|| format!("`{}` is not a valid image reference, expected `[registry[:port]/]repository[:tag][@digest]`!", reference))
+    }
+
+    fn parse_parts(reference: &str) -> Result<ImageReference> {
+        let (name_and_tag, digest) = match reference.split_once('@') {
+            Some((name_and_tag, digest)) => {
+                validate(digest, DIGEST_PATTERN, "digest")?;
+                (name_and_tag, Some(digest.to_string()))
+            },
+            None => (reference, None)
+        };
+
+        // a `:` after the last `/` starts the tag, any other `:` is part of the domain's port
+        let last_component_start = name_and_tag.rfind('/').map_or(0, |slash_position| slash_position + 1);
+        let (name, tag) = match name_and_tag[last_component_start..].rfind(':') {
+            Some(colon_position) => {
+                let colon_position = last_component_start + colon_position;
+                let tag = &name_and_tag[colon_position + 1..];
+                validate(tag, TAG_PATTERN, "tag")?;
+                (&name_and_tag[..colon_position], Some(tag.to_string()))
+            },
+            None => (name_and_tag, None)
+        };
+
+        let (domain, path) = match name.split_once('/') {
+            Some((first_component, rest)) if is_domain(first_component) => (Some(first_component.to_string()), rest),
+            _ => (None, name)
+        };
+
+        if let Some(domain) = &domain {
+            validate(domain, DOMAIN_PATTERN, "registry")?;
+        }
+        for path_component in path.split('/') {
+            validate(path_component, PATH_COMPONENT_PATTERN, "repository path component")?;
+        }
+
+        Ok(ImageReference { domain, path: path.to_string(), tag, digest })
+    }
+
+    pub fn is_docker_hub(&self) -> bool {
+        self.domain.as_deref().is_none_or(|domain| DOCKER_HUB_DOMAINS.contains(&domain))
+    }
+
+    /// The registry to talk to, `None` for Docker Hub.
+    pub fn registry_name(&self) -> Option<&str> {
+        if self.is_docker_hub() { None } else { self.domain.as_deref() }
+    }
+
+    /// The repository as the registry API expects it, official Docker Hub images live under `library/`.
+    pub fn repository_path(&self) -> String {
+        if self.is_docker_hub() && !self.path.contains('/') {
+            format!("library/{}", &self.path)
+        } else {
+            self.path.clone()
+        }
+    }
+
+    /// The last path component, e.g. `img` for `acr.io/team/sub/img:1.0`. Used as the default WSL name.
+    pub fn short_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// What to ask the registry for: the digest if there is one, otherwise the tag, otherwise `latest`.
+    pub fn manifest_reference(&self) -> &str {
+        self.digest.as_deref().or(self.tag.as_deref()).unwrap_or("latest")
+    }
+
+    /// The version part of WSL VM names: the tag, or for references pinned only by digest a short form of the digest.
+    pub fn version_label(&self) -> String {
+        match (&self.tag, &self.digest) {
+            (Some(tag), _) => tag.clone(),
+            (None, Some(digest)) => {
+                let (algorithm, hex) = digest.split_once(':').unwrap_or(("sha256", digest));
+                format!("{}-{}", algorithm, &hex[..hex.len().min(12)])
+            },
+            (None, None) => "latest".to_string()
+        }
+    }
+
+    /// The same repository with another tag and without a digest.
+    pub fn with_tag(&self, tag: &str) -> ImageReference {
+        ImageReference {
+            domain: self.domain.clone(),
+            path: self.path.clone(),
+            tag: Some(tag.to_string()),
+            digest: None
+        }
+    }
+}
+
+impl FromStr for ImageReference {
+    type Err = anyhow::Error;
+
+    fn from_str(reference: &str) -> Result<Self> {
+        ImageReference::parse(reference)
+    }
+}
+
+impl fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(domain) = &self.domain {
+            write!(f, "{}/", domain)?;
+        }
+        write!(f, "{}", &self.path)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Like the Docker CLI, the first component is a registry if it has a `.`, a port or uppercase letters, or is `localhost`.
+fn is_domain(first_component: &str) -> bool {
+    first_component.contains('.') || first_component.contains(':') || first_component == "localhost"
+        || first_component.chars().any(|c| c.is_ascii_uppercase())
+}
+
+fn validate(value: &str, pattern: &str, what: &str) -> Result<()> {
+    let regex = regex::Regex::new(pattern).unwrap();
+
+    if regex.is_match(value) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("`{}` is not a valid {}!", value, what))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn reference(domain: Option<&str>, path: &str, tag: Option<&str>, digest: Option<&str>) -> ImageReference {
+        ImageReference {
+            domain: domain.map(|d| d.to_string()),
+            path: path.to_string(),
+            tag: tag.map(|t| t.to_string()),
+            digest: digest.map(|d| d.to_string())
+        }
+    }
+
+    #[test]
+    fn parses_valid_references() {
+        let digest_reference = format!("ubuntu@{}", DIGEST);
+        let tag_and_digest_reference = format!("localhost:5000/team/img:1.0@{}", DIGEST);
+
+        let cases = [
+            ("ubuntu", reference(None, "ubuntu", None, None)),
+            ("ubuntu:22.04", reference(None, "ubuntu", Some("22.04"), None)),
+            ("alexchiri/dev-palace:main-12", reference(None, "alexchiri/dev-palace", Some("main-12"), None)),
+            ("docker.io/library/ubuntu:latest", reference(Some("docker.io"), "library/ubuntu", Some("latest"), None)),
+            ("localhost/img", reference(Some("localhost"), "img", None, None)),
+            ("localhost:5000/team/img:1.0", reference(Some("localhost:5000"), "team/img", Some("1.0"), None)),
+            ("acr.io/team/sub/img", reference(Some("acr.io"), "team/sub/img", None, None)),
+            ("myacr.azurecr.io/team/sub/img:v2.1.3-rc.1", reference(Some("myacr.azurecr.io"), "team/sub/img", Some("v2.1.3-rc.1"), None)),
+            ("127.0.0.1:5000/img:Tag_1", reference(Some("127.0.0.1:5000"), "img", Some("Tag_1"), None)),
+            ("Registry/img", reference(Some("Registry"), "img", None, None)),
+            ("team/my_img__x.y-z:1", reference(None, "team/my_img__x.y-z", Some("1"), None)),
+            (digest_reference.as_str(), reference(None, "ubuntu", None, Some(DIGEST))),
+            (tag_and_digest_reference.as_str(), reference(Some("localhost:5000"), "team/img", Some("1.0"), Some(DIGEST)))
+        ];
+
+        for (input, expected) in cases.iter() {
+            let parsed = ImageReference::parse(input).unwrap_or_else(|e| panic!("`{}` should parse: {:#}", input, e));
+            assert_eq!(&parsed, expected, "parsing `{}`", input);
+            assert_eq!(&parsed.to_string(), input, "formatting `{}`", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_references() {
+        let cases = [
+            "",
+            ":1.0",
+            "ubuntu:",
+            "Ubuntu",
+            "team/Img",
+            "localhost:5000/",
+            "team//img",
+            "img:-tag",
+            "img:tag with space",
+            "-registry.io/img",
+            "registry.io:port/img",
+            "img@sha256:abc",
+            "img@md5",
+            "img_/x",
+            "img--"
+        ];
+
+        for input in cases.iter() {
+            assert!(ImageReference::parse(input).is_err(), "`{}` should not parse", input);
+        }
+    }
+
+    #[test]
+    fn derives_names_for_registry_and_wsl_vms() {
+        let cases = [
+            ("ubuntu", None, "library/ubuntu", "ubuntu", "latest", "latest"),
+            ("docker.io/ubuntu:22.04", None, "library/ubuntu", "ubuntu", "22.04", "22.04"),
+            ("index.docker.io/alexchiri/dev:1", None, "alexchiri/dev", "dev", "1", "1"),
+            ("localhost:5000/team/img:1.0", Some("localhost:5000"), "team/img", "img", "1.0", "1.0"),
+            ("acr.io/team/sub/img", Some("acr.io"), "team/sub/img", "img", "latest", "latest")
+        ];
+
+        for (input, registry_name, repository_path, short_name, manifest_reference, version_label) in cases.iter() {
+            let parsed = ImageReference::parse(input).unwrap();
+            assert_eq!(parsed.registry_name(), *registry_name, "registry of `{}`", input);
+            assert_eq!(parsed.repository_path(), *repository_path, "repository of `{}`", input);
+            assert_eq!(parsed.short_name(), *short_name, "short name of `{}`", input);
+            assert_eq!(parsed.manifest_reference(), *manifest_reference, "manifest reference of `{}`", input);
+            assert_eq!(parsed.version_label(), *version_label, "version label of `{}`", input);
+        }
+
+        let pinned = ImageReference::parse(&format!("acr.io/team/img@{}", DIGEST)).unwrap();
+        assert_eq!(pinned.manifest_reference(), DIGEST);
+        assert_eq!(pinned.version_label(), "sha256-e3b0c44298fc");
+        assert_eq!(pinned.with_tag("2.0").to_string(), "acr.io/team/img:2.0");
+    }
+}
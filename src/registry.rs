@@ -0,0 +1,722 @@
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+const DOCKER_HUB_REGISTRY: &str = "docker.io";
+const DOCKER_HUB_API_URL: &str = "https://registry-1.docker.io";
+/// The Docker Hub web API, which knows when tags were pushed. It is not the registry and doesn't count as pulls.
+const DOCKER_HUB_WEB_API_URL: &str = "https://hub.docker.com";
+const AAD_AUTHORITY_URL: &str = "https://login.microsoftonline.com";
+const AAD_ACR_SCOPE: &str = "https://management.azure.com/.default";
+
+const MANIFEST_MEDIA_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// Credentials of a private registry as configured in .dockerwsl.
+/// For ACR, `username` and `password` are the service principal id and secret.
+#[derive(Clone)]
+pub struct RegistryCredentials {
+    pub username: String,
+    pub password: String,
+    pub tenant: Option<String>
+}
+
+impl fmt::Debug for RegistryCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RegistryCredentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .field("tenant", &self.tenant)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagDetails {
+    pub name: String,
+    /// When the tag was last pushed if the registry tells, otherwise when its image was created.
+    pub created: Option<DateTime<Utc>>
+}
+
+/// Client for the Docker Registry HTTP API v2 (ACR, Docker Hub, GHCR or any self-hosted registry).
+///
+/// Authentication follows the `WWW-Authenticate` challenge returned by the registry: basic auth, bearer tokens
+/// (anonymous or with the configured credentials) and, for ACR with a tenant, the AAD to ACR refresh token exchange.
+pub struct RegistryClient {
+    registry_name: String,
+    base_url: String,
+    docker_hub_web_api_url: String,
+    aad_authority_url: String,
+    credentials: Option<RegistryCredentials>,
+    agent: ureq::Agent,
+    authorization: Option<String>,
+    acr_refresh_token: Option<String>
+}
+
+impl RegistryClient {
+    /// Creates a client for the registry host from an image URL, `None` meaning Docker Hub.
+    pub fn new(registry_name: Option<&str>, credentials: Option<RegistryCredentials>) -> RegistryClient {
+        let registry_name = registry_name.unwrap_or(DOCKER_HUB_REGISTRY);
+        let base_url = get_registry_base_url(registry_name);
+
+        RegistryClient::with_urls(registry_name, base_url.as_str(), DOCKER_HUB_WEB_API_URL, AAD_AUTHORITY_URL, credentials)
+    }
+
+    fn with_urls(registry_name: &str, base_url: &str, docker_hub_web_api_url: &str, aad_authority_url: &str, credentials: Option<RegistryCredentials>) -> RegistryClient {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(300))
+            .build();
+
+        RegistryClient {
+            registry_name: registry_name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            docker_hub_web_api_url: docker_hub_web_api_url.trim_end_matches('/').to_string(),
+            aad_authority_url: aad_authority_url.trim_end_matches('/').to_string(),
+            credentials,
+            agent,
+            authorization: None,
+            acr_refresh_token: None
+        }
+    }
+
+    /// Official Docker Hub images live under the `library/` namespace.
+    pub fn normalize_repository_name(&self, repository_name: &str) -> String {
+        if self.registry_name == DOCKER_HUB_REGISTRY && !repository_name.contains('/') {
+            format!("library/{}", repository_name)
+        } else {
+            repository_name.to_string()
+        }
+    }
+
+    pub fn list_tags(&mut self, repository_name: &str) -> Result<Vec<String>> {
+        let repository_name = self.normalize_repository_name(repository_name);
+        let mut tags = vec![];
+        let mut next_url = Some(format!("{}/v2/{}/tags/list?n=1000", &self.base_url, &repository_name));
+
+        while let Some(url) = next_url {
+            let response = self.get(url.as_str(), None)
+                .with_context(|| format!("Could not list the tags of repository `{}`!", &repository_name))?;
+
+            next_url = self.get_next_page_url(&response);
+
+            let tags_list: serde_json::Value = response.into_json()
+                .with_context(|| format!("Could not parse the tags list of repository `{}`!", &repository_name))?;
+
+            if let Some(page_tags) = tags_list.get("tags").and_then(|t| t.as_array()) {
+                tags.extend(page_tags.iter().filter_map(|t| t.as_str()).map(|t| t.to_string()));
+            }
+        }
+
+        debug!("Repository `{}` has the tags {:?}.", &repository_name, &tags);
+        Ok(tags)
+    }
+
+    /// Lists the tags with the time they were last pushed, for the registries that have an API for it: ACR and Docker Hub.
+    /// `None` for any other registry. Unlike `get_tag_details`, no manifest is fetched, which Docker Hub counts as a pull.
+    pub fn list_tags_by_push_time(&mut self, repository_name: &str) -> Result<Option<Vec<TagDetails>>> {
+        let repository_name = self.normalize_repository_name(repository_name);
+
+        let tags = if self.registry_name.ends_with(".azurecr.io") {
+            self.list_acr_tags_by_push_time(&repository_name)?
+        } else if self.registry_name == DOCKER_HUB_REGISTRY {
+            self.list_docker_hub_tags_by_push_time(&repository_name)?
+        } else {
+            return Ok(None);
+        };
+
+        debug!("Repository `{}` has the tags {:?}.", &repository_name, &tags);
+        Ok(Some(tags))
+    }
+
+    fn list_acr_tags_by_push_time(&mut self, repository_name: &str) -> Result<Vec<TagDetails>> {
+        let mut tags = vec![];
+        let mut next_url = Some(format!("{}/acr/v1/{}/_tags?orderby=timedesc&n=100", &self.base_url, repository_name));
+
+        while let Some(url) = next_url {
+            let response = self.get(url.as_str(), None)
+                .with_context(|| format!("Could not list the tags of repository `{}` by push time!", repository_name))?;
+
+            next_url = self.get_next_page_url(&response);
+
+            let tags_list: serde_json::Value = response.into_json()
+                .with_context(|| format!("Could not parse the tags list of repository `{}`!", repository_name))?;
+
+            if let Some(page_tags) = tags_list.get("tags").and_then(|t| t.as_array()) {
+                tags.extend(page_tags.iter().filter_map(|tag| parse_pushed_tag(tag, &["lastUpdateTime", "createdTime"])));
+            }
+        }
+
+        Ok(tags)
+    }
+
+    fn list_docker_hub_tags_by_push_time(&mut self, repository_name: &str) -> Result<Vec<TagDetails>> {
+        let mut tags = vec![];
+        let mut next_url = Some(format!("{}/v2/repositories/{}/tags?page_size=100&ordering=last_updated", &self.docker_hub_web_api_url, repository_name));
+
+        while let Some(url) = next_url {
+            debug!("GET `{}`", &url);
+            // the tokens of the registry are not valid for the web API, public repositories don't need any
+            let tags_page: serde_json::Value = self.agent.get(url.as_str()).call()
+                .with_context(|| format!("Could not list the tags of repository `{}` on Docker Hub!", repository_name))?
+                .into_json()
+                .with_context(|| format!("Could not parse the tags list of repository `{}`!", repository_name))?;
+
+            next_url = tags_page.get("next").and_then(|n| n.as_str()).map(|n| n.to_string());
+
+            if let Some(page_tags) = tags_page.get("results").and_then(|r| r.as_array()) {
+                tags.extend(page_tags.iter().filter_map(|tag| parse_pushed_tag(tag, &["tag_last_pushed", "last_updated"])));
+            }
+        }
+
+        Ok(tags)
+    }
+
+    fn get_next_page_url(&self, response: &ureq::Response) -> Option<String> {
+        response.header("Link")
+            .and_then(get_next_link)
+            .map(|link| if link.starts_with('/') { format!("{}{}", &self.base_url, link) } else { link })
+    }
+
+    /// Fetches the manifest of a tag or digest. Manifest lists and image indexes are resolved to the linux/amd64 image.
+    pub fn get_manifest(&mut self, repository_name: &str, reference: &str) -> Result<serde_json::Value> {
+        let repository_name = self.normalize_repository_name(repository_name);
+        let url = format!("{}/v2/{}/manifests/{}", &self.base_url, &repository_name, reference);
+
+        let manifest: serde_json::Value = self.get(url.as_str(), Some(MANIFEST_MEDIA_TYPES))
+            .with_context(|| format!("Could not get manifest `{}` of repository `{}`!", reference, &repository_name))?
+            .into_json()
+            .with_context(|| format!("Could not parse manifest `{}` of repository `{}`!", reference, &repository_name))?;
+
+        match manifest.get("manifests").and_then(|m| m.as_array()) {
+            Some(manifests) => {
+                let image_manifest = manifests.iter()
+                    .find(|m| {
+                        let platform = m.get("platform");
+                        platform.and_then(|p| p.get("os")).and_then(|o| o.as_str()) == Some("linux")
+                            && platform.and_then(|p| p.get("architecture")).and_then(|a| a.as_str()) == Some("amd64")
+                    })
+                    .or_else(|| manifests.first())
+                    .with_context(|| format!("Manifest list `{}` of repository `{}` is empty!", reference, &repository_name))?;
+                let image_manifest_digest = image_manifest.get("digest").and_then(|d| d.as_str())
+                    .with_context(|| format!("Manifest list `{}` of repository `{}` has an entry without digest!", reference, &repository_name))?
+                    .to_string();
+
+                self.get_manifest(&repository_name, image_manifest_digest.as_str())
+            },
+            None => Ok(manifest)
+        }
+    }
+
+    /// The content digest (`sha256:...`) of a tag, for multi-platform images the digest of the manifest list.
+    pub fn get_manifest_digest(&mut self, repository_name: &str, reference: &str) -> Result<String> {
+        let repository_name = self.normalize_repository_name(repository_name);
+        let url = format!("{}/v2/{}/manifests/{}", &self.base_url, &repository_name, reference);
+
+        let response = self.request("HEAD", url.as_str(), Some(MANIFEST_MEDIA_TYPES))
+            .with_context(|| format!("Could not get manifest `{}` of repository `{}`!", reference, &repository_name))?;
+
+        match response.header("Docker-Content-Digest") {
+            Some(digest) => Ok(digest.to_string()),
+            None => {
+                // not every registry sends the digest header, the digest of the raw manifest is the same
+                let mut manifest_bytes = vec![];
+                self.get(url.as_str(), Some(MANIFEST_MEDIA_TYPES))?
+                    .into_reader()
+                    .read_to_end(&mut manifest_bytes)
+                    .with_context(|| format!("Could not read manifest `{}` of repository `{}`!", reference, &repository_name))?;
+
+                Ok(format!("sha256:{:x}", Sha256::digest(&manifest_bytes)))
+            }
+        }
+    }
+
+    /// Streams a blob (layer or image config) from the registry.
+    pub fn get_blob(&mut self, repository_name: &str, digest: &str) -> Result<Box<dyn std::io::Read + Send + Sync>> {
+        let repository_name = self.normalize_repository_name(repository_name);
+        let url = format!("{}/v2/{}/blobs/{}", &self.base_url, &repository_name, digest);
+
+        let response = self.get(url.as_str(), None)
+            .with_context(|| format!("Could not get blob `{}` of repository `{}`!", digest, &repository_name))?;
+
+        Ok(response.into_reader())
+    }
+
+    /// Determines when the image behind a tag was created, based on its image config.
+    pub fn get_tag_details(&mut self, repository_name: &str, tag: &str) -> Result<TagDetails> {
+        let manifest = self.get_manifest(repository_name, tag)?;
+
+        let config_digest = manifest.get("config").and_then(|c| c.get("digest")).and_then(|d| d.as_str())
+            .with_context(|| format!("Manifest of tag `{}` doesn't have a config digest!", tag))?
+            .to_string();
+
+        let config: serde_json::Value = serde_json::from_reader(self.get_blob(repository_name, config_digest.as_str())?)
+            .with_context(|| format!("Could not parse image config of tag `{}`!", tag))?;
+
+        let created = config.get("created").and_then(|c| c.as_str()).and_then(parse_time);
+
+        Ok(TagDetails { name: tag.to_string(), created })
+    }
+
+    fn get(&mut self, url: &str, accept: Option<&str>) -> Result<ureq::Response> {
+        self.request("GET", url, accept)
+    }
+
+    fn request(&mut self, method: &str, url: &str, accept: Option<&str>) -> Result<ureq::Response> {
+        let mut authenticated = false;
+
+        loop {
+            debug!("{} `{}`", method, url);
+            let mut request = self.agent.request(method, url);
+            if let Some(authorization) = &self.authorization {
+                request = request.set("Authorization", authorization);
+            }
+            if let Some(accept) = accept {
+                request = request.set("Accept", accept);
+            }
+
+            match request.call() {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(401, response)) if !authenticated => {
+                    let challenge = response.header("WWW-Authenticate")
+                        .with_context(|| format!("Registry `{}` returned 401 without an authentication challenge!", &self.registry_name))?
+                        .to_string();
+
+                    self.authenticate(challenge.as_str())
+                        .with_context(|| format!("Could not authenticate to registry `{}`!", &self.registry_name))?;
+                    authenticated = true;
+                },
+                Err(ureq::Error::Status(status, response)) => {
+                    let body = response.into_string().unwrap_or_default();
+                    return Err(anyhow::anyhow!("Registry `{}` returned {} for `{}`: {}", &self.registry_name, status, url, body.trim()));
+                },
+                Err(error) => {
+                    return Err(anyhow::anyhow!(error).context(format!("Could not reach registry `{}`!", &self.registry_name)));
+                }
+            }
+        }
+    }
+
+    fn authenticate(&mut self, challenge: &str) -> Result<()> {
+        debug!("Registry `{}` sent the authentication challenge `{}`.", &self.registry_name, challenge);
+        let (scheme, params) = parse_challenge(challenge);
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            let credentials = self.credentials.as_ref()
+                .with_context(|| format!("Registry `{}` requires credentials, add them to .dockerwsl!", &self.registry_name))?;
+
+            self.authorization = Some(basic_authorization(&credentials.username, &credentials.password));
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            let token = self.get_bearer_token(&params)?;
+
+            self.authorization = Some(format!("Bearer {}", token));
+        } else {
+            return Err(anyhow::anyhow!("Authentication scheme `{}` of registry `{}` is not supported!", scheme, &self.registry_name));
+        }
+
+        Ok(())
+    }
+
+    fn get_bearer_token(&mut self, params: &[(String, String)]) -> Result<String> {
+        let param = |name: &str| params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str()).unwrap_or("");
+        let realm = param("realm");
+        let service = param("service");
+        let scope = param("scope");
+
+        if realm.is_empty() {
+            return Err(anyhow::anyhow!("Bearer challenge of registry `{}` doesn't have a realm!", &self.registry_name));
+        }
+
+        let has_tenant = self.credentials.as_ref().is_some_and(|c| c.tenant.is_some());
+
+        let token_response: serde_json::Value = if has_tenant {
+            let refresh_token = self.get_acr_refresh_token()?;
+
+            self.agent.post(realm)
+                .send_form(&[("grant_type", "refresh_token"), ("service", service), ("scope", scope), ("refresh_token", refresh_token.as_str())])
+                .with_context(|| format!("Could not exchange the ACR refresh token for an access token at `{}`!", realm))?
+                .into_json()?
+        } else {
+            let mut request = self.agent.get(realm);
+            if !service.is_empty() { request = request.query("service", service); }
+            if !scope.is_empty() { request = request.query("scope", scope); }
+            if let Some(credentials) = &self.credentials {
+                request = request.set("Authorization", basic_authorization(&credentials.username, &credentials.password).as_str());
+            }
+
+            request.call()
+                .with_context(|| format!("Could not get a bearer token from `{}`!", realm))?
+                .into_json()?
+        };
+
+        let token = token_response.get("token")
+            .or_else(|| token_response.get("access_token"))
+            .and_then(|t| t.as_str())
+            .with_context(|| format!("Token endpoint `{}` didn't return a token!", realm))?;
+
+        Ok(token.to_string())
+    }
+
+    /// Logs in to AAD with the service principal and exchanges the AAD access token for an ACR refresh token.
+    fn get_acr_refresh_token(&mut self) -> Result<String> {
+        if let Some(refresh_token) = &self.acr_refresh_token {
+            return Ok(refresh_token.clone());
+        }
+
+        let credentials = self.credentials.clone()
+            .with_context(|| format!("Registry `{}` requires credentials, add them to .dockerwsl!", &self.registry_name))?;
+        let tenant = credentials.tenant.unwrap_or_default();
+
+        let aad_token_url = format!("{}/{}/oauth2/v2.0/token", &self.aad_authority_url, &tenant);
+        let aad_token_response: serde_json::Value = self.agent.post(aad_token_url.as_str())
+            .send_form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", credentials.username.as_str()),
+                ("client_secret", credentials.password.as_str()),
+                ("scope", AAD_ACR_SCOPE)
+            ])
+            .with_context(|| format!("Could not log in to Azure with the service principal of registry `{}`. Double-check the service principal details in `.dockerwsl`!", &self.registry_name))?
+            .into_json()?;
+        let aad_access_token = aad_token_response.get("access_token").and_then(|t| t.as_str())
+            .with_context(|| "Azure login didn't return an access token!")?;
+
+        let exchange_url = format!("{}/oauth2/exchange", &self.base_url);
+        let exchange_response: serde_json::Value = self.agent.post(exchange_url.as_str())
+            .send_form(&[
+                ("grant_type", "access_token"),
+                ("service", self.registry_name.as_str()),
+                ("tenant", tenant.as_str()),
+                ("access_token", aad_access_token)
+            ])
+            .with_context(|| format!("Could not exchange the Azure access token for an ACR refresh token at `{}`!", &exchange_url))?
+            .into_json()?;
+        let refresh_token = exchange_response.get("refresh_token").and_then(|t| t.as_str())
+            .with_context(|| format!("Registry `{}` didn't return a refresh token!", &self.registry_name))?
+            .to_string();
+
+        self.acr_refresh_token = Some(refresh_token.clone());
+        Ok(refresh_token)
+    }
+}
+
+fn get_registry_base_url(registry_name: &str) -> String {
+    if registry_name == DOCKER_HUB_REGISTRY || registry_name == "index.docker.io" || registry_name == "registry-1.docker.io" {
+        DOCKER_HUB_API_URL.to_string()
+    } else if registry_name.starts_with("localhost") || registry_name.starts_with("127.0.0.1") {
+        // like docker, local registries are expected to be plain http
+        format!("http://{}", registry_name)
+    } else {
+        format!("https://{}", registry_name)
+    }
+}
+
+/// A tag from the ACR or Docker Hub tags list, with the first of `time_keys` it has as the time it was pushed.
+fn parse_pushed_tag(tag: &serde_json::Value, time_keys: &[&str]) -> Option<TagDetails> {
+    let name = tag.get("name").and_then(|n| n.as_str())?;
+    let created = time_keys.iter().find_map(|time_key| tag.get(*time_key).and_then(|t| t.as_str()).and_then(parse_time));
+
+    Some(TagDetails { name: name.to_string(), created })
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time).ok().map(|time| time.with_timezone(&Utc))
+}
+
+fn basic_authorization(username: &str, password: &str) -> String {
+    format!("Basic {}", base64::encode(format!("{}:{}", username, password)))
+}
+
+/// Splits a `WWW-Authenticate` header like `Bearer realm="...",service="...",scope="..."` into its scheme and parameters.
+fn parse_challenge(challenge: &str) -> (String, Vec<(String, String)>) {
+    let challenge = challenge.trim();
+    let (scheme, params_str) = match challenge.find(' ') {
+        Some(index) => (&challenge[..index], &challenge[index + 1..]),
+        None => (challenge, "")
+    };
+
+    let params_regex = regex::Regex::new(r#"(\w+)="([^"]*)""#).unwrap();
+    let params = params_regex.captures_iter(params_str)
+        .map(|c| (c[1].to_string(), c[2].to_string()))
+        .collect();
+
+    (scheme.to_string(), params)
+}
+
+/// Extracts the next page URL from a `Link: </v2/...>; rel="next"` header.
+fn get_next_link(link_header: &str) -> Option<String> {
+    link_header.split(',')
+        .find(|link| link.contains("rel=\"next\""))
+        .and_then(|link| {
+            let start = link.find('<')?;
+            let end = link.find('>')?;
+            Some(link[start + 1..end].to_string())
+        })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    pub(crate) struct MockRequest {
+        pub method: String,
+        pub path: String,
+        pub headers: Vec<(String, String)>,
+        pub body: String
+    }
+
+    impl MockRequest {
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+        }
+    }
+
+    pub(crate) struct MockResponse {
+        pub status: u16,
+        pub headers: Vec<(String, String)>,
+        pub body: Vec<u8>
+    }
+
+    impl MockResponse {
+        pub fn json(status: u16, body: serde_json::Value) -> MockResponse {
+            MockResponse { status, headers: vec![("Content-Type".to_string(), "application/json".to_string())], body: body.to_string().into_bytes() }
+        }
+
+        pub fn bytes(body: Vec<u8>) -> MockResponse {
+            MockResponse { status: 200, headers: vec![], body }
+        }
+
+        pub fn with_header(mut self, name: &str, value: &str) -> MockResponse {
+            self.headers.push((name.to_string(), value.to_string()));
+            self
+        }
+    }
+
+    /// Starts a minimal HTTP server on a random local port answering every request with the given handler.
+    /// Returns its base URL and the log of received requests as `METHOD path`.
+    pub(crate) fn start_mock_server<F>(handler: F) -> (String, Arc<Mutex<Vec<String>>>)
+    where F: Fn(&MockRequest, &str) -> MockResponse + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let request_log = Arc::new(Mutex::new(vec![]));
+        let thread_base_url = base_url.clone();
+        let thread_request_log = request_log.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream { Ok(s) => s, Err(_) => continue };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).is_err() { continue; }
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or("").to_string();
+                let path = parts.next().unwrap_or("").to_string();
+
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).is_err() || line.trim().is_empty() { break; }
+                    if let Some((k, v)) = line.split_once(':') {
+                        headers.push((k.trim().to_string(), v.trim().to_string()));
+                    }
+                }
+
+                let content_length = headers.iter().find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, v)| v.parse::<usize>().ok()).unwrap_or(0);
+                let mut body = vec![0; content_length];
+                let _ = reader.read_exact(&mut body);
+
+                let request = MockRequest { method, path, headers, body: String::from_utf8_lossy(&body).to_string() };
+                thread_request_log.lock().unwrap().push(format!("{} {}", &request.method, &request.path));
+                let response = handler(&request, thread_base_url.as_str());
+
+                let mut head = format!("HTTP/1.1 {} MOCK\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+                for (k, v) in response.headers.iter() {
+                    head.push_str(format!("{}: {}\r\n", k, v).as_str());
+                }
+                head.push_str("\r\n");
+
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&response.body);
+            }
+        });
+
+        (base_url, request_log)
+    }
+
+    fn mock_client(base_url: &str, credentials: Option<RegistryCredentials>) -> RegistryClient {
+        mock_registry_client("mock.registry.io", base_url, credentials)
+    }
+
+    /// A client talking to the mock server as if it was `registry_name`, also for the Docker Hub web API and AAD.
+    pub(crate) fn mock_registry_client(registry_name: &str, base_url: &str, credentials: Option<RegistryCredentials>) -> RegistryClient {
+        RegistryClient::with_urls(registry_name, base_url, base_url, format!("{}/aad", base_url).as_str(), credentials)
+    }
+
+    pub(crate) fn config_blob(created: &str) -> serde_json::Value {
+        serde_json::json!({ "architecture": "amd64", "os": "linux", "created": created })
+    }
+
+    #[test]
+    fn lists_tags_with_anonymous_bearer_token_and_pagination() {
+        let (base_url, request_log) = start_mock_server(|request, base_url| {
+            if request.path.starts_with("/token") {
+                assert_eq!(request.path, "/token?service=mock.registry.io&scope=repository%3Ateam%2Fdev%3Apull");
+                return MockResponse::json(200, serde_json::json!({ "token": "anonymous-token" }));
+            }
+            if request.header("Authorization") != Some("Bearer anonymous-token") {
+                let challenge = format!(r#"Bearer realm="{}/token",service="mock.registry.io",scope="repository:team/dev:pull""#, base_url);
+                return MockResponse::json(401, serde_json::json!({})).with_header("WWW-Authenticate", challenge.as_str());
+            }
+            match request.path.as_str() {
+                "/v2/team/dev/tags/list?n=1000" => MockResponse::json(200, serde_json::json!({ "name": "team/dev", "tags": ["1.0", "1.1"] }))
+                    .with_header("Link", r#"</v2/team/dev/tags/list?last=1.1&n=1000>; rel="next""#),
+                "/v2/team/dev/tags/list?last=1.1&n=1000" => MockResponse::json(200, serde_json::json!({ "name": "team/dev", "tags": ["main-3"] })),
+                _ => MockResponse::json(404, serde_json::json!({}))
+            }
+        });
+
+        let tags = mock_client(base_url.as_str(), None).list_tags("team/dev").unwrap();
+
+        assert_eq!(tags, vec!["1.0", "1.1", "main-3"]);
+        assert_eq!(request_log.lock().unwrap().iter().filter(|r| r.starts_with("GET /token")).count(), 1);
+    }
+
+    #[test]
+    fn uses_basic_auth_when_challenged() {
+        let (base_url, _) = start_mock_server(|request, _| {
+            if request.header("Authorization") != Some(basic_authorization("user", "secret").as_str()) {
+                return MockResponse::json(401, serde_json::json!({})).with_header("WWW-Authenticate", r#"Basic realm="registry""#);
+            }
+            MockResponse::json(200, serde_json::json!({ "name": "dev", "tags": ["1.0"] }))
+        });
+
+        let credentials = RegistryCredentials { username: "user".to_string(), password: "secret".to_string(), tenant: None };
+        let tags = mock_client(base_url.as_str(), Some(credentials)).list_tags("dev").unwrap();
+
+        assert_eq!(tags, vec!["1.0"]);
+    }
+
+    #[test]
+    fn fails_basic_auth_without_credentials() {
+        let (base_url, _) = start_mock_server(|_, _| {
+            MockResponse::json(401, serde_json::json!({})).with_header("WWW-Authenticate", r#"Basic realm="registry""#)
+        });
+
+        assert!(mock_client(base_url.as_str(), None).list_tags("dev").is_err());
+    }
+
+    #[test]
+    fn exchanges_acr_refresh_token_with_service_principal() {
+        let (base_url, request_log) = start_mock_server(|request, base_url| {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("POST", "/aad/my-tenant/oauth2/v2.0/token") => {
+                    assert!(request.body.contains("client_id=sp-id"));
+                    assert!(request.body.contains("client_secret=sp-secret"));
+                    MockResponse::json(200, serde_json::json!({ "access_token": "aad-token" }))
+                },
+                ("POST", "/oauth2/exchange") => {
+                    assert!(request.body.contains("access_token=aad-token"));
+                    assert!(request.body.contains("tenant=my-tenant"));
+                    MockResponse::json(200, serde_json::json!({ "refresh_token": "acr-refresh-token" }))
+                },
+                ("POST", "/oauth2/token") => {
+                    assert!(request.body.contains("grant_type=refresh_token"));
+                    assert!(request.body.contains("refresh_token=acr-refresh-token"));
+                    MockResponse::json(200, serde_json::json!({ "access_token": "acr-access-token" }))
+                },
+                _ if request.header("Authorization") == Some("Bearer acr-access-token") => {
+                    MockResponse::json(200, serde_json::json!({ "name": "dev", "tags": ["2.0"] }))
+                },
+                _ => {
+                    let challenge = format!(r#"Bearer realm="{}/oauth2/token",service="mock.registry.io",scope="repository:dev:pull""#, base_url);
+                    MockResponse::json(401, serde_json::json!({})).with_header("WWW-Authenticate", challenge.as_str())
+                }
+            }
+        });
+
+        let credentials = RegistryCredentials { username: "sp-id".to_string(), password: "sp-secret".to_string(), tenant: Some("my-tenant".to_string()) };
+        let tags = mock_client(base_url.as_str(), Some(credentials)).list_tags("dev").unwrap();
+
+        assert_eq!(tags, vec!["2.0"]);
+        assert!(request_log.lock().unwrap().contains(&"POST /oauth2/exchange".to_string()));
+    }
+
+    #[test]
+    fn determines_tag_creation_date_from_image_config() {
+        let (base_url, _) = start_mock_server(|request, _| {
+            match request.path.as_str() {
+                "/v2/dev/manifests/old" => MockResponse::json(200, serde_json::json!({ "schemaVersion": 2, "config": { "digest": "sha256:old" }, "layers": [] })),
+                "/v2/dev/manifests/new" => MockResponse::json(200, serde_json::json!({ "schemaVersion": 2, "config": { "digest": "sha256:new" }, "layers": [] })),
+                "/v2/dev/manifests/multi" => MockResponse::json(200, serde_json::json!({ "schemaVersion": 2, "manifests": [
+                    { "digest": "sha256:arm", "platform": { "os": "linux", "architecture": "arm64" } },
+                    { "digest": "sha256:amd", "platform": { "os": "linux", "architecture": "amd64" } }
+                ]})),
+                "/v2/dev/manifests/sha256:amd" => MockResponse::json(200, serde_json::json!({ "schemaVersion": 2, "config": { "digest": "sha256:multi" }, "layers": [] })),
+                "/v2/dev/blobs/sha256:old" => MockResponse::json(200, config_blob("2020-10-01T10:00:00Z")),
+                "/v2/dev/blobs/sha256:new" => MockResponse::json(200, config_blob("2020-11-20T08:30:00.123456789Z")),
+                "/v2/dev/blobs/sha256:multi" => MockResponse::json(200, config_blob("2020-11-02T10:00:00+02:00")),
+                _ => MockResponse::json(404, serde_json::json!({}))
+            }
+        });
+
+        let mut client = mock_client(base_url.as_str(), None);
+        let created = |client: &mut RegistryClient, tag: &str| client.get_tag_details("dev", tag).unwrap().created.unwrap().to_rfc3339();
+
+        assert_eq!(created(&mut client, "old"), "2020-10-01T10:00:00+00:00");
+        assert_eq!(created(&mut client, "new"), "2020-11-20T08:30:00.123456789+00:00");
+        assert_eq!(created(&mut client, "multi"), "2020-11-02T08:00:00+00:00");
+    }
+
+    #[test]
+    fn resolves_manifest_digest() {
+        let manifest = serde_json::json!({ "schemaVersion": 2, "config": { "digest": "sha256:config" }, "layers": [] });
+        let manifest_digest = format!("sha256:{:x}", Sha256::digest(manifest.to_string().as_bytes()));
+        let (base_url, _) = start_mock_server(move |request, _| {
+            match (request.method.as_str(), request.path.as_str()) {
+                ("HEAD", "/v2/dev/manifests/with-header") => MockResponse::json(200, serde_json::json!({}))
+                    .with_header("Docker-Content-Digest", "sha256:from-header"),
+                ("HEAD", "/v2/dev/manifests/without-header") => MockResponse::json(200, serde_json::json!({})),
+                ("GET", "/v2/dev/manifests/without-header") => MockResponse::json(200, manifest.clone()),
+                _ => MockResponse::json(404, serde_json::json!({}))
+            }
+        });
+
+        let mut client = mock_client(base_url.as_str(), None);
+
+        assert_eq!(client.get_manifest_digest("dev", "with-header").unwrap(), "sha256:from-header");
+        assert_eq!(client.get_manifest_digest("dev", "without-header").unwrap(), manifest_digest);
+    }
+
+    #[test]
+    fn normalizes_docker_hub_official_images() {
+        let client = RegistryClient::new(None, None);
+
+        assert_eq!(client.normalize_repository_name("ubuntu"), "library/ubuntu");
+        assert_eq!(client.normalize_repository_name("alexchiri/dev"), "alexchiri/dev");
+        assert_eq!(client.base_url, DOCKER_HUB_API_URL);
+        assert_eq!(RegistryClient::new(Some("localhost:5000"), None).base_url, "http://localhost:5000");
+        assert_eq!(RegistryClient::new(Some("ghcr.io"), None).base_url, "https://ghcr.io");
+    }
+
+    #[test]
+    fn parses_challenges_and_links() {
+        let (scheme, params) = parse_challenge(r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/ubuntu:pull""#);
+
+        assert_eq!(scheme, "Bearer");
+        assert_eq!(params[0], ("realm".to_string(), "https://auth.docker.io/token".to_string()));
+        assert_eq!(params[2], ("scope".to_string(), "repository:library/ubuntu:pull".to_string()));
+        assert_eq!(get_next_link(r#"</v2/dev/tags/list?last=b&n=2>; rel="next""#), Some("/v2/dev/tags/list?last=b&n=2".to_string()));
+        assert_eq!(get_next_link(r#"</v2/dev/tags/list>; rel="prev""#), None);
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::{Serialize, Deserialize};
+
+use crate::output;
+use crate::progress::{self, Progress};
+use crate::reference::ImageReference;
+
+/// How images are pulled and turned into WSL VM tars, configured with `runtime` in .dockerwsl or `--runtime`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Runtime {
+    /// Downloads the image layers from the registry and flattens them, no container runtime needed.
+    #[default]
+    Builtin,
+    /// Docker CLI, needs a running Docker Desktop or Docker engine.
+    Docker,
+    /// Podman CLI.
+    Podman,
+    /// nerdctl CLI (containerd), e.g. from Rancher Desktop.
+    Nerdctl
+}
+
+impl Runtime {
+    /// The container runtime CLI to use, `None` for the builtin runtime.
+    pub fn container_runtime(self) -> Option<Box<dyn ContainerRuntime>> {
+        match self {
+            Runtime::Builtin => None,
+            Runtime::Docker => Some(Box::new(Docker)),
+            Runtime::Podman => Some(Box::new(Podman)),
+            Runtime::Nerdctl => Some(Box::new(Nerdctl))
+        }
+    }
+}
+
+impl FromStr for Runtime {
+    type Err = anyhow::Error;
+
+    fn from_str(runtime: &str) -> Result<Self> {
+        match runtime.to_lowercase().as_str() {
+            "builtin" => Ok(Runtime::Builtin),
+            "docker" => Ok(Runtime::Docker),
+            "podman" => Ok(Runtime::Podman),
+            "nerdctl" => Ok(Runtime::Nerdctl),
+            _ => Err(anyhow::anyhow!("Unknown runtime `{}`, valid options are builtin, docker, podman and nerdctl!", runtime))
+        }
+    }
+}
+
+/// A container runtime CLI that can pull an image and export a container created from it to a tar.
+/// Docker, Podman and nerdctl share the Docker CLI syntax, so implementations only override what differs.
+pub trait ContainerRuntime {
+    fn binary(&self) -> &'static str;
+
+    /// The image URL as the runtime expects it.
+    fn qualify_image_url(&self, image_url_str: &str) -> String {
+        image_url_str.to_string()
+    }
+
+    fn login(&self, registry_name: &str, username: &str, password: &str) -> Result<()> {
+        let binary = self.binary();
+        let mut login_command = Command::new(binary);
+        login_command.args(["login", registry_name])
+                     .args(["--username", username])
+                     .arg("--password-stdin")
+                     .stdin(Stdio::piped());
+
+        // on the command line the password would be visible to every other process
+        let mut login_process = output::spawn_child(&mut login_command)
+            .with_context(|| format!("`{} login {}` failed!", binary, registry_name))?;
+        login_process.child.stdin.take().unwrap().write_all(password.as_bytes())
+            .with_context(|| format!("Could not pass the password to `{} login {}`!", binary, registry_name))?;
+
+        let login_command_status = login_process.wait()
+            .with_context(|| format!("`{} login {}` failed!", binary, registry_name))?;
+        if !login_command_status.success() {
+            return Err(anyhow::anyhow!("`{} login {}` failed. Double-check the service principal details in `.dockerwsl`!", binary, registry_name));
+        }
+
+        Ok(())
+    }
+
+    fn pull(&self, image_url_str: &str) -> Result<()> {
+        let binary = self.binary();
+        let image_url = self.qualify_image_url(image_url_str);
+        let mut pull_command = Command::new(binary);
+        pull_command.args(["pull", image_url.as_str()]);
+
+        let pull_command_status = output::spawn_child(&mut pull_command)
+            .and_then(output::ChildProcess::wait)
+            .with_context(|| format!("`{} pull {}` failed!", binary, &image_url))?;
+        if !pull_command_status.success() {
+            return Err(anyhow::anyhow!("`{} pull {}` failed!", binary, &image_url));
+        }
+
+        Ok(())
+    }
+
+    /// The registry digest (`sha256:...`) of a pulled image, `None` for images that were never pulled from a registry.
+    fn image_digest(&self, image_url_str: &str) -> Result<Option<String>> {
+        let binary = self.binary();
+        let image_url = self.qualify_image_url(image_url_str);
+        let mut inspect_command = Command::new(binary);
+        inspect_command.args(["image", "inspect", "--format", "{{join .RepoDigests \"\\n\"}}", image_url.as_str()]);
+
+        let inspect_command_output = inspect_command.output()
+            .with_context(|| format!("`{} image inspect {}` failed!", binary, &image_url))?;
+        if !inspect_command_output.status.success() {
+            return Err(anyhow::anyhow!("`{} image inspect {}` failed!", binary, &image_url));
+        }
+
+        let stdout_string = String::from_utf8(inspect_command_output.stdout)
+            .with_context(|| "Couldn't parse stdout!")?;
+
+        Ok(stdout_string.lines()
+            .filter_map(|repo_digest| repo_digest.trim().split_once('@'))
+            .map(|(_, digest)| digest.to_string())
+            .next())
+    }
+
+    /// Creates a container (without starting it) and returns its id.
+    fn create(&self, image_url_str: &str) -> Result<String> {
+        let binary = self.binary();
+        let image_url = self.qualify_image_url(image_url_str);
+        let mut create_command = Command::new(binary);
+        create_command.args(["create", image_url.as_str()]);
+
+        let create_command_output = create_command.output()
+            .with_context(|| format!("`{} create {}` failed!", binary, &image_url))?;
+        if !create_command_output.status.success() {
+            return Err(anyhow::anyhow!("`{} create {}` failed: {}", binary, &image_url, String::from_utf8_lossy(&create_command_output.stderr).trim()));
+        }
+
+        let stdout_string = String::from_utf8(create_command_output.stdout)
+            .with_context(|| "Couldn't parse stdout!")?;
+
+        Ok(stdout_string.trim().replace(char::from(0), ""))
+    }
+
+    fn export(&self, container_id: &str, tar_file_path: &Path) -> Result<()> {
+        let binary = self.binary();
+        let tar_file_path_str = tar_file_path.to_str()
+            .with_context(|| format!("Could not convert path `{}` to &str!", tar_file_path.display()))?;
+
+        let mut export_command = Command::new(binary);
+        export_command.args(["container", "export"]);
+        export_command.args(["-o", tar_file_path_str]);
+        export_command.arg(container_id);
+
+        let mut progress = Progress::start(&format!("Exporting the container to `{}`", tar_file_path_str), None);
+        let export_command_status = output::spawn_child(&mut export_command)
+            .and_then(|export_process| progress::wait_for_child(export_process, &mut progress, Some(tar_file_path)))
+            .with_context(|| format!("`{} container export -o {} {}` failed!", binary, tar_file_path_str, container_id))?;
+        if !export_command_status.success() {
+            return Err(anyhow::anyhow!("Could not `{} container export -o {} {}`!", binary, tar_file_path_str, container_id));
+        }
+        progress.finish();
+
+        Ok(())
+    }
+
+    fn remove(&self, container_id: &str) -> Result<()> {
+        let binary = self.binary();
+        let mut remove_command = Command::new(binary);
+        remove_command.args(["rm", container_id]);
+
+        let remove_command_output = remove_command.output()
+            .with_context(|| format!("`{} rm {}` failed!", binary, container_id))?;
+        if !remove_command_output.status.success() {
+            return Err(anyhow::anyhow!("Could not `{} rm {}`!", binary, container_id));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Docker;
+
+impl ContainerRuntime for Docker {
+    fn binary(&self) -> &'static str { "docker" }
+}
+
+pub struct Podman;
+
+impl ContainerRuntime for Podman {
+    fn binary(&self) -> &'static str { "podman" }
+
+    /// Podman asks which registry to use for short names, Docker Hub is what the image URLs in .dockerwsl mean.
+    fn qualify_image_url(&self, image_url_str: &str) -> String {
+        match ImageReference::parse(image_url_str) {
+            Ok(image_reference) if image_reference.domain.is_none() => format!("docker.io/{}", image_url_str),
+            _ => image_url_str.to_string()
+        }
+    }
+}
+
+pub struct Nerdctl;
+
+impl ContainerRuntime for Nerdctl {
+    fn binary(&self) -> &'static str { "nerdctl" }
+}
@@ -0,0 +1,151 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::debug;
+use tempfile::Builder;
+
+use crate::dry_run;
+
+/// Held while a file is read, changed and written back, so dragon processes (and `--jobs`) changing the same file
+/// don't overwrite each other's changes. Released when dropped.
+///
+/// The lock is taken on `<file>.dragon-lock` next to the file, the file itself is replaced on every write.
+pub struct FileLock {
+    _lock_file: Option<File>
+}
+
+/// Takes the lock of `file_path`, waiting for whoever has it. A dry run changes nothing, so it takes no lock.
+pub fn lock(file_path: &Path) -> Result<FileLock> {
+    if dry_run::is_enabled() {
+        return Ok(FileLock { _lock_file: None });
+    }
+
+    let lock_file_path = sibling_path(file_path, ".dragon-lock");
+    let lock_file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&lock_file_path)
+        .with_context(|| format!("Could not open lock file `{}`!", lock_file_path.display()))?;
+
+    match lock_file.try_lock() {
+        Ok(()) => {},
+        Err(TryLockError::WouldBlock) => {
+            message!("Waiting for `{}`, it is being changed by another dragon process...", file_path.display());
+            lock_file.lock()
+                .with_context(|| format!("Could not lock `{}`!", lock_file_path.display()))?;
+        },
+        Err(TryLockError::Error(error)) => {
+            return Err(error).with_context(|| format!("Could not lock `{}`!", lock_file_path.display()));
+        }
+    }
+
+    debug!("Locked `{}`.", file_path.display());
+    Ok(FileLock { _lock_file: Some(lock_file) })
+}
+
+/// Replaces the content of `file_path` in one go: the content is written to a temporary file next to it, which is then
+/// renamed over it. A crash in the middle leaves either the old or the new file, never half of one.
+///
+/// A symlink, like a settings.json linked from a dotfiles repo, is followed and the file it points to is replaced,
+/// so the link stays in place.
+pub fn write(file_path: &Path, content: &[u8]) -> Result<()> {
+    let target_path = if file_path.exists() {
+        fs::canonicalize(file_path)
+            .with_context(|| format!("Could not resolve the path of `{}`!", file_path.display()))?
+    } else {
+        file_path.to_path_buf()
+    };
+    let file_path = target_path.as_path();
+
+    let folder = match file_path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new(".")
+    };
+    let file_name = file_path.file_name()
+        .with_context(|| format!("`{}` is not a file path!", file_path.display()))?;
+
+    let mut temp_file = Builder::new().prefix(file_name).suffix(".tmp").tempfile_in(folder)
+        .with_context(|| format!("Could not create a temporary file in `{}`!", folder.display()))?;
+    temp_file.write_all(content)
+        .and_then(|_| temp_file.as_file().sync_all())
+        .with_context(|| format!("Could not write temporary file `{}`!", temp_file.path().display()))?;
+
+    temp_file.persist(file_path)
+        .with_context(|| format!("Could not replace `{}`!", file_path.display()))?;
+
+    Ok(())
+}
+
+/// Like `write`, keeping the previous version of the file as `<file>.bak`.
+pub fn write_with_backup(file_path: &Path, content: &[u8]) -> Result<()> {
+    if file_path.exists() {
+        let backup_path = sibling_path(file_path, ".bak");
+        fs::copy(file_path, &backup_path)
+            .with_context(|| format!("Could not back up `{}` to `{}`!", file_path.display(), backup_path.display()))?;
+    }
+
+    write(file_path, content)
+}
+
+fn sibling_path(file_path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling_name = file_path.file_name().map(|file_name| file_name.to_os_string()).unwrap_or_default();
+    sibling_name.push(suffix);
+
+    file_path.with_file_name(sibling_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_files_and_keeps_a_backup() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join(".dockerwsl");
+
+        write_with_backup(&file_path, b"wsls: []\n").unwrap();
+        assert!(!sibling_path(&file_path, ".bak").exists());
+
+        let _file_lock = lock(&file_path).unwrap();
+        write_with_backup(&file_path, b"wsls:\n  - name: dev\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "wsls:\n  - name: dev\n");
+        assert_eq!(fs::read_to_string(sibling_path(&file_path, ".bak")).unwrap(), "wsls: []\n");
+
+        let mut file_names: Vec<String> = fs::read_dir(temp_dir.path()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        file_names.sort();
+        assert_eq!(file_names, vec![".dockerwsl", ".dockerwsl.bak", ".dockerwsl.dragon-lock"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let target_path = temp_dir.path().join("team").join(".dockerwsl");
+        let link_path = temp_dir.path().join(".dockerwsl");
+        fs::create_dir(temp_dir.path().join("team")).unwrap();
+        fs::write(&target_path, "wsls: []\n").unwrap();
+        std::os::unix::fs::symlink(&target_path, &link_path).unwrap();
+
+        write_with_backup(&link_path, b"wsls:\n  - name: dev\n").unwrap();
+
+        assert!(fs::symlink_metadata(&link_path).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target_path).unwrap(), "wsls:\n  - name: dev\n");
+        assert_eq!(fs::read_to_string(sibling_path(&link_path, ".bak")).unwrap(), "wsls: []\n");
+    }
+
+    #[test]
+    fn lock_is_exclusive_until_dropped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("settings.json");
+        let lock_file_path = sibling_path(&file_path, ".dragon-lock");
+
+        let file_lock = lock(&file_path).unwrap();
+        let other_handle = File::open(&lock_file_path).unwrap();
+        assert!(matches!(other_handle.try_lock(), Err(TryLockError::WouldBlock)));
+
+        drop(file_lock);
+        assert!(other_handle.try_lock().is_ok());
+    }
+}
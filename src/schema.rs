@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+
+/// Version of the .dockerwsl schema this dragon reads and writes, kept in the `version` key.
+pub const CURRENT_VERSION: u64 = 1;
+
+const VERSION_KEY: &str = "version";
+
+/// The migration at index `n` takes a .dockerwsl document from version `n` to version `n + 1`.
+/// Version 0 are the files written before the schema had a version.
+const MIGRATIONS: [fn(&mut Mapping); CURRENT_VERSION as usize] = [
+    migrate_unversioned
+];
+
+/// Parses the text of a .dockerwsl file and migrates it to the current version. Returns the migrated document and the version it had.
+pub fn parse(dockerwsl_text: &str) -> Result<(Value, u64)> {
+    let mut dockerwsl_document: Value = if dockerwsl_text.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_yaml::from_str(dockerwsl_text).with_context(|| "Could not parse yaml file!")?
+    };
+
+    // an empty or missing .dockerwsl is a new file, not one written before the schema had a version
+    if dockerwsl_document.is_null() {
+        return Ok((new_document(), CURRENT_VERSION));
+    }
+
+    let version = migrate(&mut dockerwsl_document)?;
+
+    Ok((dockerwsl_document, version))
+}
+
+/// Runs the migrations a document needs one after the other, each one bumping its `version`. Returns the version it had.
+fn migrate(dockerwsl_document: &mut Value) -> Result<u64> {
+    let dockerwsl_mapping = dockerwsl_document.as_mapping_mut()
+        .with_context(|| "A .dockerwsl file has to be a yaml mapping, like `wsls: []`!")?;
+
+    let version = match dockerwsl_mapping.get(&Value::from(VERSION_KEY)) {
+        Some(version) => version.as_u64()
+            .with_context(|| format!("The .dockerwsl `{}` has to be a whole number!", VERSION_KEY))?,
+        None => 0
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(anyhow::anyhow!("The .dockerwsl file has schema version {}, this dragon only knows up to version {}! Upgrade dragon to use it.", version, CURRENT_VERSION));
+    }
+
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(dockerwsl_mapping);
+        dockerwsl_mapping.insert(Value::from(VERSION_KEY), Value::from(from_version as u64 + 1));
+    }
+
+    Ok(version)
+}
+
+/// A .dockerwsl with the current version and nothing configured yet.
+fn new_document() -> Value {
+    let mut dockerwsl_mapping = Mapping::new();
+    dockerwsl_mapping.insert(Value::from(VERSION_KEY), Value::from(CURRENT_VERSION));
+    dockerwsl_mapping.insert(Value::from("wsls"), Value::Sequence(vec![]));
+    dockerwsl_mapping.insert(Value::from("private_registries"), Value::Sequence(vec![]));
+
+    Value::Mapping(dockerwsl_mapping)
+}
+
+/// Turns lists left without any item, like `private_registries:`, into empty lists and sets the missing `runtime` to docker, the only one there was.
+fn migrate_unversioned(dockerwsl_mapping: &mut Mapping) {
+    for list_key in ["wsls", "private_registries"].iter() {
+        let list_key = Value::from(*list_key);
+        if dockerwsl_mapping.get(&list_key).is_none_or(Value::is_null) {
+            dockerwsl_mapping.insert(list_key, Value::Sequence(vec![]));
+        }
+    }
+
+    let runtime_key = Value::from("runtime");
+    if dockerwsl_mapping.get(&runtime_key).is_none_or(Value::is_null) {
+        dockerwsl_mapping.insert(runtime_key, Value::from("docker"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_unversioned_files() {
+        let (dockerwsl_document, version) = parse("wsls:\n  - name: dev\nprivate_registries:\n").unwrap();
+
+        assert_eq!(version, 0);
+        assert_eq!(dockerwsl_document, serde_yaml::from_str::<Value>("wsls:\n  - name: dev\nprivate_registries: []\nruntime: docker\nversion: 1\n").unwrap());
+        assert_eq!(parse("runtime: podman\nwsls: []\n").unwrap().0.get("runtime"), Some(&Value::from("podman")));
+    }
+
+    #[test]
+    fn leaves_current_files_and_rejects_newer_ones() {
+        let current = "version: 1\nwsls: []\nprivate_registries: []\n";
+        assert_eq!(parse(current).unwrap(), (serde_yaml::from_str::<Value>(current).unwrap(), 1));
+        // a new file starts out with the current version, without the docker runtime of the unversioned files
+        assert_eq!(parse("").unwrap(), (serde_yaml::from_str::<Value>(current).unwrap(), 1));
+
+        assert!(parse("version: 2\nwsls: []\n").is_err());
+        assert!(parse("version: two\nwsls: []\n").is_err());
+        assert!(parse("- dev\n").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use log::debug;
+use serde::de::{self, value::MapAccessDeserializer, Deserializer, MapAccess, Visitor};
+use serde::{Serialize, Serializer, Deserialize};
+
+use crate::registry::{RegistryClient, TagDetails};
+
+/// How many tags the `newest` policy fetches the image config of, on registries that can't list tags by push time.
+const MAX_INSPECTED_TAGS: usize = 50;
+
+/// How `dragon update` picks the tag that is written to the `latest` property of a WSL.
+///
+/// In .dockerwsl it looks like `tag_policy: newest`, `tag_policy: { semver: { range: "^2.1" } }`
+/// or `tag_policy: { regex: { include: '^main-\d+$', exclude: '-rc$' } }`. `semver` and `regex` without any
+/// setting can be written by name only, like `tag_policy: semver`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", remote = "Self")]
+pub enum TagPolicy {
+    /// The most recently pushed tag. This is what is used when no policy is configured.
+    #[default]
+    Newest,
+    /// The highest semantic version tag (a leading `v` is allowed), optionally constrained by a range like `^2.1`.
+    /// Tags that are not semantic versions are ignored.
+    Semver {
+        range: Option<String>
+    },
+    /// The most recently pushed tag among the tags matching `include` and not matching `exclude`.
+    Regex {
+        include: Option<String>,
+        exclude: Option<String>
+    }
+}
+
+impl Serialize for TagPolicy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        TagPolicy::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TagPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(TagPolicyVisitor)
+    }
+}
+
+/// Reads a policy name on its own, the derived deserializer only takes it for policies without settings.
+struct TagPolicyVisitor;
+
+impl<'de> Visitor<'de> for TagPolicyVisitor {
+    type Value = TagPolicy;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("`newest`, `semver`, `regex` or a policy with settings like `{ semver: { range: \"^2.1\" } }`")
+    }
+
+    fn visit_str<E: de::Error>(self, policy_name: &str) -> std::result::Result<TagPolicy, E> {
+        match policy_name {
+            "newest" => Ok(TagPolicy::Newest),
+            "semver" => Ok(TagPolicy::Semver { range: None }),
+            "regex" => Ok(TagPolicy::Regex { include: None, exclude: None }),
+            _ => Err(E::unknown_variant(policy_name, &["newest", "semver", "regex"]))
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, policy: A) -> std::result::Result<TagPolicy, A::Error> {
+        TagPolicy::deserialize(MapAccessDeserializer::new(policy))
+    }
+}
+
+impl TagPolicy {
+    pub fn select_tag(&self, registry_client: &mut RegistryClient, repository_name: &str) -> Result<String> {
+        debug!("Selecting the latest tag of repository `{}` with policy {:?}.", repository_name, self);
+
+        let tags = registry_client.list_tags(repository_name)?;
+
+        let selected_tag = match self {
+            TagPolicy::Newest => select_newest(registry_client, repository_name, &tags)?,
+            TagPolicy::Semver { range } => select_highest_semver(&tags, range.as_deref())?,
+            TagPolicy::Regex { include, exclude } => {
+                let filtered_tags = filter_tags(&tags, include.as_deref(), exclude.as_deref())?;
+                select_newest(registry_client, repository_name, &filtered_tags)?
+            }
+        };
+
+        selected_tag.with_context(|| format!("No tag of repository `{}` matches the tag policy {:?}!", repository_name, self))
+    }
+}
+
+/// Picks the most recently pushed of `tags`. Registries that can list their tags by push time (ACR and Docker Hub) are asked
+/// for that. Any other registry has the image config of every tag fetched instead, which is one manifest and blob per tag,
+/// so only the last `MAX_INSPECTED_TAGS` of them are looked at.
+fn select_newest(registry_client: &mut RegistryClient, repository_name: &str, tags: &[String]) -> Result<Option<String>> {
+    let tag_details = match registry_client.list_tags_by_push_time(repository_name) {
+        Ok(Some(pushed_tags)) => pushed_tags.into_iter().filter(|tag| tags.contains(&tag.name)).collect(),
+        Ok(None) => inspect_tags(registry_client, repository_name, tags),
+        Err(error) => {
+            debug!("Could not list the tags of repository `{}` by push time, inspecting them instead: {:#}", repository_name, error);
+            inspect_tags(registry_client, repository_name, tags)
+        }
+    };
+
+    Ok(newest_tag(tag_details))
+}
+
+fn inspect_tags(registry_client: &mut RegistryClient, repository_name: &str, tags: &[String]) -> Vec<TagDetails> {
+    let inspected_tags = &tags[tags.len().saturating_sub(MAX_INSPECTED_TAGS)..];
+    if inspected_tags.len() < tags.len() {
+        message!("Repository `{}` has {} tags and its registry can't list them by push time, so only the last {} in the order the registry lists them (often by name, not by age) are looked at and a newer tag may be missed. Use a `semver` or `regex` tag policy to pick from all of them.",
+            repository_name, tags.len(), MAX_INSPECTED_TAGS);
+    }
+
+    inspected_tags.iter()
+        .filter_map(|tag| match registry_client.get_tag_details(repository_name, tag) {
+            Ok(tag_details) => Some(tag_details),
+            Err(error) => {
+                // signatures, attestations and other artifacts have no image config
+                debug!("Skipping tag `{}` of repository `{}`: {:#}", tag, repository_name, error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The tag with the latest time. Tags pushed or built at the same time, like reproducible builds, are told apart by name.
+fn newest_tag(tag_details: Vec<TagDetails>) -> Option<String> {
+    tag_details.into_iter()
+        .max_by(|a, b| a.created.cmp(&b.created).then_with(|| a.name.cmp(&b.name)))
+        .map(|tag| tag.name)
+}
+
+fn select_highest_semver(tags: &[String], range: Option<&str>) -> Result<Option<String>> {
+    let version_req = match range {
+        Some(range) => semver::VersionReq::parse(range)
+            .with_context(|| format!("`{}` is not a valid semver range!", range))?,
+        None => semver::VersionReq::STAR
+    };
+
+    let highest_tag = tags.iter()
+        .filter_map(|tag| {
+            let version = semver::Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()?;
+            Some((version, tag))
+        })
+        .filter(|(version, _)| version_req.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag.clone());
+
+    Ok(highest_tag)
+}
+
+fn filter_tags(tags: &[String], include: Option<&str>, exclude: Option<&str>) -> Result<Vec<String>> {
+    let compile = |pattern: Option<&str>| -> Result<Option<regex::Regex>> {
+        match pattern {
+            Some(pattern) => Ok(Some(regex::Regex::new(pattern)
+                .with_context(|| format!("`{}` is not a valid regex!", pattern))?)),
+            None => Ok(None)
+        }
+    };
+    let include_regex = compile(include)?;
+    let exclude_regex = compile(exclude)?;
+
+    let filtered_tags = tags.iter()
+        .filter(|tag| include_regex.as_ref().is_none_or(|r| r.is_match(tag)))
+        .filter(|tag| !exclude_regex.as_ref().is_some_and(|r| r.is_match(tag)))
+        .cloned()
+        .collect();
+
+    Ok(filtered_tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{config_blob, mock_registry_client, start_mock_server, MockResponse};
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn selects_highest_semver_tag() {
+        let tags = tags(&["latest", "pr-123", "1.9.0", "v2.1.3", "2.10.0", "2.2.0-rc.1", "cache"]);
+
+        assert_eq!(select_highest_semver(&tags, None).unwrap(), Some("2.10.0".to_string()));
+        assert_eq!(select_highest_semver(&tags, Some("^1")).unwrap(), Some("1.9.0".to_string()));
+        assert_eq!(select_highest_semver(&tags, Some("~2.1")).unwrap(), Some("v2.1.3".to_string()));
+        assert_eq!(select_highest_semver(&tags, Some("^3")).unwrap(), None);
+        assert!(select_highest_semver(&tags, Some("not a range")).is_err());
+    }
+
+    #[test]
+    fn filters_tags_with_include_and_exclude() {
+        let tags = tags(&["main-1", "main-12", "main-12-debug", "pr-123", "cache"]);
+
+        assert_eq!(filter_tags(&tags, Some(r"^main-\d+$"), None).unwrap(), vec!["main-1", "main-12"]);
+        assert_eq!(filter_tags(&tags, Some("^main-"), Some("debug")).unwrap(), vec!["main-1", "main-12"]);
+        assert_eq!(filter_tags(&tags, None, Some("^(pr-|cache)")).unwrap(), vec!["main-1", "main-12", "main-12-debug"]);
+        assert!(filter_tags(&tags, Some("("), None).is_err());
+    }
+
+    #[test]
+    fn selects_newest_tag_from_image_configs() {
+        let (base_url, _) = start_mock_server(|request, _| {
+            let manifest = |config_digest: &str| MockResponse::json(200, serde_json::json!({ "schemaVersion": 2, "config": { "digest": config_digest }, "layers": [] }));
+            match request.path.as_str() {
+                "/v2/dev/tags/list?n=1000" => MockResponse::json(200, serde_json::json!({ "name": "dev", "tags": ["1.0", "1.1", "1.1-repro", "sha256-abc.sig", "0.9"] })),
+                "/v2/dev/manifests/1.0" => manifest("sha256:old"),
+                "/v2/dev/manifests/0.9" => manifest("sha256:older"),
+                "/v2/dev/manifests/1.1" | "/v2/dev/manifests/1.1-repro" => manifest("sha256:new"),
+                // a signature has no image config
+                "/v2/dev/manifests/sha256-abc.sig" => MockResponse::json(200, serde_json::json!({ "schemaVersion": 2, "layers": [] })),
+                "/v2/dev/blobs/sha256:older" => MockResponse::json(200, config_blob("2020-09-01T10:00:00Z")),
+                "/v2/dev/blobs/sha256:old" => MockResponse::json(200, config_blob("2020-10-01T10:00:00Z")),
+                "/v2/dev/blobs/sha256:new" => MockResponse::json(200, config_blob("2020-11-01T10:00:00Z")),
+                _ => MockResponse::json(404, serde_json::json!({}))
+            }
+        });
+
+        let mut registry_client = mock_registry_client("mock.registry.io", base_url.as_str(), None);
+
+        assert_eq!(TagPolicy::Newest.select_tag(&mut registry_client, "dev").unwrap(), "1.1-repro");
+        let regex = TagPolicy::Regex { include: Some(r"^\d+\.\d+$".to_string()), exclude: Some(r"^1\.1$".to_string()) };
+        assert_eq!(regex.select_tag(&mut registry_client, "dev").unwrap(), "1.0");
+    }
+
+    #[test]
+    fn selects_newest_tag_by_push_time_without_fetching_manifests() {
+        let (base_url, request_log) = start_mock_server(|request, _| {
+            match request.path.as_str() {
+                "/v2/dev/tags/list?n=1000" | "/v2/library/ubuntu/tags/list?n=1000" => MockResponse::json(200, serde_json::json!({ "tags": ["1.0", "2.0", "nightly"] })),
+                "/acr/v1/dev/_tags?orderby=timedesc&n=100" => MockResponse::json(200, serde_json::json!({ "tags": [
+                    { "name": "nightly", "lastUpdateTime": "2020-12-01T10:00:00Z" }
+                ]})).with_header("Link", r#"</acr/v1/dev/_tags?last=nightly&n=100&orderby=timedesc>; rel="next""#),
+                "/acr/v1/dev/_tags?last=nightly&n=100&orderby=timedesc" => MockResponse::json(200, serde_json::json!({ "tags": [
+                    { "name": "2.0", "lastUpdateTime": "2020-11-01T10:00:00Z" },
+                    { "name": "1.0", "lastUpdateTime": "2020-10-01T10:00:00Z" }
+                ]})),
+                "/v2/repositories/library/ubuntu/tags?page_size=100&ordering=last_updated" => MockResponse::json(200, serde_json::json!({ "next": null, "results": [
+                    { "name": "2.0", "tag_last_pushed": "2020-11-01T10:00:00Z" },
+                    { "name": "1.0", "tag_last_pushed": "2020-12-01T10:00:00Z" },
+                    { "name": "nightly", "tag_last_pushed": "2020-10-01T10:00:00Z" }
+                ]})),
+                _ => MockResponse::json(404, serde_json::json!({}))
+            }
+        });
+
+        let mut acr_client = mock_registry_client("myacr.azurecr.io", base_url.as_str(), None);
+        let mut docker_hub_client = mock_registry_client("docker.io", base_url.as_str(), None);
+        let release = TagPolicy::Regex { include: Some(r"^\d".to_string()), exclude: None };
+
+        assert_eq!(TagPolicy::Newest.select_tag(&mut acr_client, "dev").unwrap(), "nightly");
+        assert_eq!(release.select_tag(&mut acr_client, "dev").unwrap(), "2.0");
+        assert_eq!(TagPolicy::Newest.select_tag(&mut docker_hub_client, "ubuntu").unwrap(), "1.0");
+        assert!(!request_log.lock().unwrap().iter().any(|request| request.contains("/manifests/") || request.contains("/blobs/")));
+    }
+
+    #[test]
+    fn deserializes_tag_policies() {
+        let newest: TagPolicy = serde_yaml::from_str("newest").unwrap();
+        let semver: TagPolicy = serde_yaml::from_str("semver:\n  range: ^2.1").unwrap();
+        let regex: TagPolicy = serde_yaml::from_str("regex:\n  include: '^main-\\d+$'").unwrap();
+
+        assert_eq!(newest, TagPolicy::Newest);
+        assert_eq!(semver, TagPolicy::Semver { range: Some("^2.1".to_string()) });
+        assert_eq!(regex, TagPolicy::Regex { include: Some(r"^main-\d+$".to_string()), exclude: None });
+        assert_eq!(serde_yaml::from_str::<TagPolicy>("semver").unwrap(), TagPolicy::Semver { range: None });
+        assert_eq!(serde_yaml::from_str::<TagPolicy>("regex").unwrap(), TagPolicy::Regex { include: None, exclude: None });
+        assert!(serde_yaml::from_str::<TagPolicy>("oldest").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+/// A Windows Terminal installation, each has its own settings.json file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowsTerminalInstall {
+    /// Windows Terminal from the Microsoft Store or winget.
+    Stable,
+    /// Windows Terminal Preview from the Microsoft Store or winget.
+    Preview,
+    /// Windows Terminal installed from a zip, Scoop or Chocolatey.
+    Unpackaged,
+    /// A settings.json file passed with `--wtconfig`.
+    Custom
+}
+
+impl WindowsTerminalInstall {
+    pub const KNOWN: [WindowsTerminalInstall; 3] = [WindowsTerminalInstall::Stable, WindowsTerminalInstall::Preview, WindowsTerminalInstall::Unpackaged];
+
+    /// Where the install keeps its settings.json file, `None` for custom ones or if `LOCALAPPDATA` is not set.
+    pub fn settings_path(self) -> Option<PathBuf> {
+        let local_app_data = PathBuf::from(std::env::var_os("LOCALAPPDATA")?);
+
+        match self {
+            WindowsTerminalInstall::Stable => Some(local_app_data.join("Packages").join("Microsoft.WindowsTerminal_8wekyb3d8bbwe").join("LocalState").join("settings.json")),
+            WindowsTerminalInstall::Preview => Some(local_app_data.join("Packages").join("Microsoft.WindowsTerminalPreview_8wekyb3d8bbwe").join("LocalState").join("settings.json")),
+            WindowsTerminalInstall::Unpackaged => Some(local_app_data.join("Microsoft").join("Windows Terminal").join("settings.json")),
+            WindowsTerminalInstall::Custom => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowsTerminalInstall::Stable => "stable",
+            WindowsTerminalInstall::Preview => "preview",
+            WindowsTerminalInstall::Unpackaged => "unpackaged",
+            WindowsTerminalInstall::Custom => "custom"
+        }
+    }
+
+    /// The install a settings.json file belongs to, `Custom` if it is not at any of the known locations.
+    pub fn from_settings_path(settings_path: &Path) -> WindowsTerminalInstall {
+        WindowsTerminalInstall::KNOWN.iter()
+            .find(|install| install.settings_path().is_some_and(|known_path| known_path == settings_path))
+            .copied()
+            .unwrap_or(WindowsTerminalInstall::Custom)
+    }
+
+    /// The installs, out of `selected_installs`, that have a settings.json file on this machine.
+    pub fn discover(selected_installs: &[WindowsTerminalInstall]) -> Vec<(WindowsTerminalInstall, PathBuf)> {
+        selected_installs.iter()
+            .filter_map(|install| install.settings_path().map(|settings_path| (*install, settings_path)))
+            .filter(|(_install, settings_path)| settings_path.exists())
+            .collect()
+    }
+}
+
+/// Which settings.json file of which install has a profile for a WSL, tracked so `dragon remove` finds all of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowsTerminalProfileState {
+    pub install: WindowsTerminalInstall,
+    pub settings_path: String
+}